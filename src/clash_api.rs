@@ -1,41 +1,46 @@
 use anyhow::{Context, Result};
-use reqwest::Client;
+use reqwest::Method;
 use indexmap::IndexMap;
-
-use crate::config::Config;
-use crate::models::{ProxyGroup, ProxiesResponse, SwitchRequest};
-
+use std::time::Duration;
+use serde::de::DeserializeOwned;
+use tokio::sync::{mpsc, watch};
+use tracing::warn;
+
+use crate::config::ControllerConfig;
+use crate::secret::Secret;
+use crate::transport::{ApiResponse, Transport, REQUEST_TIMEOUT};
+use crate::models::{
+    CoreConfig, CoreConfigPatch, CoreFlavour, CoreVersion, DelayResponse, ErrorResponse, GroupType,
+    LogEntry, MemoryStats, NodeInfo, ProxyGroup, ProxiesResponse, ReloadRequest, SwitchRequest,
+    VersionResponse,
+};
+
+#[derive(Clone)]
 pub struct ClashApi {
-    client: Client,
-    base_url: String,
-    secret: String,
+    transport: Transport,
+    secret: Secret,
+    flavour: Option<CoreFlavour>,
 }
 
 impl ClashApi {
-    pub fn new(config: &Config) -> Result<Self> {
-        let client = Client::builder()
-            .timeout(std::time::Duration::from_secs(5))
-            .build()
-            .context("Failed to create HTTP client")?;
+    pub fn new(controller: &ControllerConfig) -> Result<Self> {
+        let transport = Transport::new(controller)?;
+        let secret = Secret::load(controller)
+            .with_context(|| format!("Failed to load the secret for {}", controller.name))?;
 
         Ok(ClashApi {
-            client,
-            base_url: config.clash_api_url.clone(),
-            secret: config.clash_secret.clone(),
+            transport,
+            secret,
+            flavour: None,
         })
     }
 
-    pub async fn get_proxies(&self) -> Result<(IndexMap<String, ProxyGroup>, IndexMap<String, u32>)> {
-        let url = format!("{}/proxies", self.base_url);
-
-        let mut request = self.client.get(&url);
-
-        if !self.secret.is_empty() {
-            request = request.header("Authorization", format!("Bearer {}", self.secret));
-        }
-
-        let response = request
-            .send()
+    /// Returns every proxy group whose type is listed in `group_types`, plus
+    /// a catalog of every proxy with its type, flags and latest delay.
+    pub async fn get_proxies(&self, group_types: &[String]) -> Result<(IndexMap<String, ProxyGroup>, IndexMap<String, NodeInfo>)> {
+        let response = self
+            .transport
+            .send(Method::GET, "/proxies", &self.secret, None, Some(REQUEST_TIMEOUT))
             .await
             .context("Failed to send request to Clash API")?;
 
@@ -49,50 +54,58 @@ impl ClashApi {
             .context("Failed to parse proxies response")?;
 
         let mut proxy_groups = IndexMap::new();
-        let mut node_delays = IndexMap::new();
+        let mut nodes = IndexMap::new();
 
-        // Extract delay information from all proxies
+        // Build the node catalog, including the latest delay of each proxy
         for (name, proxy) in &proxies_response.proxies {
-            if let Some(ref history) = proxy.history {
-                if let Some(latest) = history.last() {
-                    node_delays.insert(name.clone(), latest.delay);
-                }
-            }
+            let delay = proxy
+                .history
+                .as_ref()
+                .and_then(|history| history.last())
+                .map(|latest| latest.delay);
+
+            nodes.insert(name.clone(), NodeInfo {
+                name: name.clone(),
+                node_type: proxy.proxy_type.clone(),
+                delay,
+                udp: proxy.udp.unwrap_or(false),
+                xudp: proxy.xudp.unwrap_or(false),
+                tfo: proxy.tfo.unwrap_or(false),
+                provider: proxy.provider_name.clone().filter(|provider| !provider.is_empty()),
+                dialer_proxy: proxy.dialer_proxy.clone().filter(|dialer| !dialer.is_empty()),
+            });
         }
 
-        // Filter for proxy groups (Selector, URLTest, Fallback)
+        // Groups are the proxies with members; keep the configured types
         for (name, proxy) in proxies_response.proxies {
-            if proxy.proxy_type == "Selector" || proxy.proxy_type == "URLTest" || proxy.proxy_type == "Fallback" {
+            if proxy.all.is_some() && group_types.contains(&proxy.proxy_type) {
                 // Convert Proxy to ProxyGroup
                 let group = ProxyGroup {
                     name: proxy.name,
-                    group_type: proxy.proxy_type,
+                    group_type: GroupType::from(proxy.proxy_type),
                     now: proxy.now,
                     all: proxy.all,
+                    fixed: proxy.fixed,
+                    strategy: proxy.strategy,
+                    hidden: proxy.hidden,
+                    icon: proxy.icon,
                 };
                 proxy_groups.insert(name, group);
             }
         }
 
-        Ok((proxy_groups, node_delays))
+        Ok((proxy_groups, nodes))
     }
 
     pub async fn switch_node(&self, group_name: &str, node_name: &str) -> Result<()> {
-        let url = format!("{}/proxies/{}", self.base_url, group_name);
-
-        let mut request = self.client.put(&url);
-
-        if !self.secret.is_empty() {
-            request = request.header("Authorization", format!("Bearer {}", self.secret));
-        }
-
+        let path = api_path(&["proxies", group_name], &[]);
         let switch_request = SwitchRequest {
             name: node_name.to_string(),
         };
 
-        let response = request
-            .json(&switch_request)
-            .send()
+        let response = self
+            .transport
+            .send(Method::PUT, &path, &self.secret, Some(&serde_json::to_value(&switch_request)?), Some(REQUEST_TIMEOUT))
             .await
             .context("Failed to send switch request to Clash API")?;
 
@@ -103,41 +116,295 @@ impl ClashApi {
         Ok(())
     }
 
-    pub async fn test_connection(&self) -> Result<()> {
-        let url = format!("{}/proxies", self.base_url);
+    /// Clears a pinned node on a URLTest/Fallback group (mihomo only).
+    pub async fn unpin_group(&self, group_name: &str) -> Result<()> {
+        if let Some(flavour) = self.flavour {
+            if !flavour.supports_pinning() {
+                anyhow::bail!("{} does not support unpinning groups", flavour.name());
+            }
+        }
 
-        let mut request = self.client.get(&url);
+        let path = api_path(&["group", group_name], &[]);
+
+        let response = self
+            .transport
+            .send(Method::DELETE, &path, &self.secret, None, Some(REQUEST_TIMEOUT))
+            .await
+            .context("Failed to send unpin request to Clash API")?;
 
-        if !self.secret.is_empty() {
-            request = request.header("Authorization", format!("Bearer {}", self.secret));
+        if !response.status().is_success() {
+            anyhow::bail!("Failed to unpin group: {}", response.status());
         }
 
-        let response = request
-            .send()
+        Ok(())
+    }
+
+    /// Fetches `/version` and detects which core flavour serves the API.
+    pub async fn version(&self) -> Result<CoreVersion> {
+        let response = self
+            .transport
+            .send(Method::GET, "/version", &self.secret, None, Some(REQUEST_TIMEOUT))
             .await
-            .context("Failed to connect to Clash API")?;
+            .context("Failed to fetch Clash version")?;
 
         if !response.status().is_success() {
             anyhow::bail!("Clash API returned error: {}", response.status());
         }
 
+        let version_response: VersionResponse = response
+            .json()
+            .await
+            .context("Failed to parse version response")?;
+
+        Ok(CoreVersion {
+            flavour: CoreFlavour::detect(&version_response),
+            version: version_response.version,
+        })
+    }
+
+    /// Remembers the detected core flavour so unsupported endpoints fail early.
+    pub fn set_flavour(&mut self, flavour: CoreFlavour) {
+        self.flavour = Some(flavour);
+    }
+
+    /// Streams `/memory` in a background task. The receiver holds the latest
+    /// sample; the task reconnects on errors and stops, closing the
+    /// connection, as soon as the receiver is dropped.
+    pub fn stream_memory(&self) -> Result<watch::Receiver<Option<MemoryStats>>> {
+        if let Some(flavour) = self.flavour {
+            if !flavour.supports_memory() {
+                anyhow::bail!("{} does not report memory usage", flavour.name());
+            }
+        }
+
+        let (sender, receiver) = watch::channel(None);
+        let transport = self.transport.clone();
+        let secret = self.secret.clone();
+
+        tokio::spawn(async move {
+            loop {
+                let read = Self::read_json_stream(&transport, "/memory", &secret, |stats: MemoryStats| {
+                    sender.send(Some(stats)).is_ok()
+                });
+                tokio::select! {
+                    result = read => {
+                        if let Err(e) = result {
+                            warn!("Memory stream interrupted: {}", e);
+                        }
+                    }
+                    _ = sender.closed() => break,
+                }
+                sender.send(None).ok();
+                tokio::select! {
+                    _ = tokio::time::sleep(Duration::from_secs(5)) => {}
+                    _ = sender.closed() => break,
+                }
+            }
+        });
+
+        Ok(receiver)
+    }
+
+    /// Streams `/logs` at the given level in a background task. Lines are
+    /// dropped while the receiver is full; the task reconnects on errors and
+    /// stops, closing the connection, as soon as the receiver is dropped.
+    pub fn stream_logs(&self, level: &str) -> mpsc::Receiver<LogEntry> {
+        let (sender, receiver) = mpsc::channel(256);
+        let transport = self.transport.clone();
+        let path = api_path(&["logs"], &[("level", level)]);
+        let secret = self.secret.clone();
+
+        tokio::spawn(async move {
+            // A quiet stream sends nothing for a long time, so watch for the
+            // receiver going away instead of waiting for the next line
+            loop {
+                let read = Self::read_json_stream(&transport, &path, &secret, |entry: LogEntry| {
+                    !matches!(sender.try_send(entry), Err(mpsc::error::TrySendError::Closed(_)))
+                });
+                tokio::select! {
+                    result = read => {
+                        if let Err(e) = result {
+                            warn!("Log stream interrupted: {}", e);
+                        }
+                    }
+                    _ = sender.closed() => break,
+                }
+                tokio::select! {
+                    _ = tokio::time::sleep(Duration::from_secs(5)) => {}
+                    _ = sender.closed() => break,
+                }
+            }
+        });
+
+        receiver
+    }
+
+    /// Reads a streaming endpoint that writes one JSON object per line,
+    /// until the stream ends or `on_item` returns false.
+    async fn read_json_stream<T: DeserializeOwned>(
+        transport: &Transport,
+        path: &str,
+        secret: &Secret,
+        mut on_item: impl FnMut(T) -> bool,
+    ) -> Result<()> {
+        let mut response = transport
+            .send(Method::GET, path, secret, None, None)
+            .await
+            .context("Failed to connect to stream")?;
+
+        if !response.status().is_success() {
+            anyhow::bail!("Stream returned error: {}", response.status());
+        }
+
+        let mut buffer = Vec::new();
+        while let Some(chunk) = response.chunk().await.context("Failed to read stream")? {
+            buffer.extend_from_slice(&chunk);
+            while let Some(newline) = buffer.iter().position(|&byte| byte == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=newline).collect();
+                if let Ok(item) = serde_json::from_slice::<T>(&line) {
+                    if !on_item(item) {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+
         Ok(())
     }
 
-    pub async fn trigger_delay_test(&self, group_name: &str, url: &str, timeout: u32) -> Result<()> {
-        let api_url = format!(
-            "{}/group/{}/delay?url={}&timeout={}",
-            self.base_url, group_name, url, timeout
-        );
+    pub async fn get_configs(&self) -> Result<CoreConfig> {
+        let response = self
+            .transport
+            .send(Method::GET, "/configs", &self.secret, None, Some(REQUEST_TIMEOUT))
+            .await
+            .context("Failed to fetch core configs")?;
+
+        if !response.status().is_success() {
+            anyhow::bail!("Clash API returned error: {}", response.status());
+        }
+
+        response
+            .json()
+            .await
+            .context("Failed to parse configs response")
+    }
 
-        let mut request = self.client.get(&api_url);
+    pub async fn patch_configs(&self, patch: &CoreConfigPatch) -> Result<()> {
+        let response = self
+            .transport
+            .send(Method::PATCH, "/configs", &self.secret, Some(&serde_json::to_value(patch)?), Some(REQUEST_TIMEOUT))
+            .await
+            .context("Failed to send config patch to Clash API")?;
 
-        if !self.secret.is_empty() {
-            request = request.header("Authorization", format!("Bearer {}", self.secret));
+        if !response.status().is_success() {
+            anyhow::bail!("Failed to update core config: {}", response.status());
         }
 
-        let response = request
-            .send()
+        Ok(())
+    }
+
+    /// Reloads the core config from `path`, or from its current file when empty.
+    pub async fn reload_config(&self, path: &str) -> Result<()> {
+        let reload_request = ReloadRequest {
+            path: path.to_string(),
+            payload: String::new(),
+        };
+
+        let response = self
+            .transport
+            .send(Method::PUT, "/configs?force=true", &self.secret, Some(&serde_json::to_value(&reload_request)?), Some(REQUEST_TIMEOUT))
+            .await
+            .context("Failed to send reload request to Clash API")?;
+
+        if !response.status().is_success() {
+            return Err(self.error_from_response(response, "Failed to reload config").await);
+        }
+
+        Ok(())
+    }
+
+    pub async fn restart(&self) -> Result<()> {
+        if let Some(flavour) = self.flavour {
+            if !flavour.supports_restart() {
+                anyhow::bail!("{} does not support restarting via the API", flavour.name());
+            }
+        }
+
+        let body = serde_json::json!({});
+        let response = self
+            .transport
+            .send(Method::POST, "/restart", &self.secret, Some(&body), Some(REQUEST_TIMEOUT))
+            .await
+            .context("Failed to send restart request to Clash API")?;
+
+        if !response.status().is_success() {
+            return Err(self.error_from_response(response, "Failed to restart core").await);
+        }
+
+        Ok(())
+    }
+
+    /// Downloads fresh GeoIP/GeoSite databases (mihomo only).
+    pub async fn update_geo(&self) -> Result<()> {
+        if let Some(flavour) = self.flavour {
+            if !flavour.supports_geo_update() {
+                anyhow::bail!("{} does not support updating geo databases", flavour.name());
+            }
+        }
+
+        // Geo downloads can take a while, so allow more than the default timeout
+        let body = serde_json::json!({});
+        let response = self
+            .transport
+            .send(Method::POST, "/configs/geo", &self.secret, Some(&body), Some(Duration::from_secs(120)))
+            .await
+            .context("Failed to send geo update request to Clash API")?;
+
+        if !response.status().is_success() {
+            return Err(self.error_from_response(response, "Failed to update geo databases").await);
+        }
+
+        Ok(())
+    }
+
+    /// Builds an error that includes the core's `message`, falling back to
+    /// the raw body and the HTTP status.
+    async fn error_from_response(&self, response: ApiResponse, action: &str) -> anyhow::Error {
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        let message = serde_json::from_str::<ErrorResponse>(&body)
+            .map(|error| error.message)
+            .unwrap_or(body);
+        // The core may echo request details back, so never surface the secret
+        let message = self.secret.redact(&message);
+
+        if message.trim().is_empty() {
+            anyhow::anyhow!("{}: {}", action, status)
+        } else {
+            anyhow::anyhow!("{}: {} ({})", action, message.trim(), status)
+        }
+    }
+
+    pub async fn test_connection(&self) -> Result<()> {
+        let response = self
+            .transport
+            .send(Method::GET, "/proxies", &self.secret, None, Some(REQUEST_TIMEOUT))
+            .await
+            .context("Failed to connect to Clash API")?;
+
+        if !response.status().is_success() {
+            anyhow::bail!("Clash API returned error: {}", response.status());
+        }
+
+        Ok(())
+    }
+
+    pub async fn trigger_delay_test(&self, group_name: &str, url: &str, timeout: u32) -> Result<()> {
+        let path = api_path(&["group", group_name, "delay"], &[("url", url), ("timeout", &timeout.to_string())]);
+
+        let response = self
+            .transport
+            .send(Method::GET, &path, &self.secret, None, Some(REQUEST_TIMEOUT))
             .await
             .context("Failed to trigger delay test")?;
 
@@ -147,4 +414,43 @@ impl ClashApi {
 
         Ok(())
     }
+
+    pub async fn test_node_delay(&self, node_name: &str, url: &str, timeout: u32) -> Result<u32> {
+        let path = api_path(&["proxies", node_name, "delay"], &[("url", url), ("timeout", &timeout.to_string())]);
+
+        let response = self
+            .transport
+            .send(Method::GET, &path, &self.secret, None, Some(REQUEST_TIMEOUT))
+            .await
+            .context("Failed to test node delay")?;
+
+        if !response.status().is_success() {
+            anyhow::bail!("Node delay test returned error: {}", response.status());
+        }
+
+        let delay_response: DelayResponse = response
+            .json()
+            .await
+            .context("Failed to parse delay response")?;
+
+        Ok(delay_response.delay)
+    }
+}
+
+/// Builds a request path with every segment and query value percent-encoded,
+/// so names like "HK #1" or "a/b" stay a single segment.
+fn api_path(segments: &[&str], query: &[(&str, &str)]) -> String {
+    let mut url = reqwest::Url::parse("http://localhost").expect("valid base URL");
+    url.path_segments_mut()
+        .expect("HTTP URLs have a path")
+        .clear()
+        .extend(segments);
+    if !query.is_empty() {
+        url.query_pairs_mut().extend_pairs(query);
+    }
+
+    match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_string(),
+    }
 }
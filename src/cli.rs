@@ -0,0 +1,257 @@
+use anyhow::{Context, Result};
+use indexmap::IndexMap;
+use std::path::PathBuf;
+use tracing::warn;
+
+use crate::clash_api::ClashApi;
+use crate::config::{Config, ConfigOptions, ControllerConfig};
+use crate::presets;
+use crate::state::Selections;
+use crate::validate;
+
+/// Splits the global options off the command-line arguments:
+/// `--controller <name>`, `--config <path>`, repeatable `--set key=value`
+/// and `--verbose` (`-v`, twice for trace output).
+pub fn take_global_options(args: &[String]) -> Result<(Option<String>, ConfigOptions, u8, Vec<String>)> {
+    let mut controller = None;
+    let mut options = ConfigOptions::default();
+    let mut verbose = 0;
+    let mut rest = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--controller" | "-c" => {
+                controller = Some(args.next().context("--controller requires a value")?.clone());
+            }
+            "--config" | "-C" => {
+                options.path = Some(PathBuf::from(args.next().context("--config requires a path")?));
+            }
+            "--set" => {
+                options.overrides.push(args.next().context("--set requires key=value")?.clone());
+            }
+            "--verbose" | "-v" => verbose += 1,
+            "-vv" => verbose += 2,
+            _ => rest.push(arg.clone()),
+        }
+    }
+
+    Ok((controller, options, verbose, rest))
+}
+
+/// Loads the config and connects to a controller (the first one by default),
+/// detecting its flavour so unsupported commands fail with a clear message.
+pub async fn connect(controller: Option<&str>) -> Result<(Config, ClashApi, ControllerConfig)> {
+    let config = Config::load().context("Failed to load configuration")?;
+    let controller_config = config.controller(controller)?;
+    let mut clash_api = ClashApi::new(&controller_config).context("Failed to create Clash API client")?;
+    clash_api
+        .test_connection()
+        .await
+        .context("Cannot connect to Clash API")?;
+
+    if let Ok(version) = clash_api.version().await {
+        clash_api.set_flavour(version.flavour);
+    }
+
+    Ok((config, clash_api, controller_config))
+}
+
+/// `clbar reload [--path <path>]`
+pub async fn reload(args: &[String], controller: Option<&str>) -> Result<()> {
+    let mut path = String::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--path" | "-p" => {
+                path = args.next().context("--path requires a value")?.clone();
+            }
+            other => anyhow::bail!("Unknown argument for reload: {}", other),
+        }
+    }
+
+    let (_, clash_api, _) = connect(controller).await?;
+    clash_api.reload_config(&path).await?;
+
+    if path.is_empty() {
+        println!("Reloaded core config");
+    } else {
+        println!("Reloaded core config from {}", path);
+    }
+    Ok(())
+}
+
+/// `clbar restart`
+pub async fn restart(args: &[String], controller: Option<&str>) -> Result<()> {
+    if let Some(arg) = args.first() {
+        anyhow::bail!("Unknown argument for restart: {}", arg);
+    }
+
+    let (_, clash_api, _) = connect(controller).await?;
+    clash_api.restart().await?;
+
+    println!("Restarting core");
+    Ok(())
+}
+
+/// `clbar update-geo`
+pub async fn update_geo(args: &[String], controller: Option<&str>) -> Result<()> {
+    if let Some(arg) = args.first() {
+        anyhow::bail!("Unknown argument for update-geo: {}", arg);
+    }
+
+    let (_, clash_api, _) = connect(controller).await?;
+    clash_api.update_geo().await?;
+
+    println!("Updated GeoIP/GeoSite databases");
+    Ok(())
+}
+
+/// `clbar preset <name>`, `clbar preset --save <name>` or `clbar preset --list`
+pub async fn preset(args: &[String], controller: Option<&str>) -> Result<()> {
+    let (mut config, clash_api, controller_config) = connect(controller).await?;
+
+    match args.first().map(String::as_str) {
+        Some("--list") | Some("-l") => {
+            for preset in &config.presets {
+                println!("{}", preset.name);
+            }
+            Ok(())
+        }
+        Some("--save") | Some("-s") => {
+            let name = args.get(1).context("--save requires a preset name")?;
+            let (proxy_groups, _) = clash_api.get_proxies(&config.group_types).await?;
+            let preset = presets::from_current(name, &proxy_groups);
+            let group_count = preset.groups.len();
+            config.presets.retain(|existing| existing.name != *name);
+            config.presets.push(preset);
+            config.save()?;
+
+            println!("Saved preset {} with {} groups", name, group_count);
+            Ok(())
+        }
+        Some(name) => {
+            let preset = config
+                .presets
+                .iter()
+                .find(|preset| preset.name == name)
+                .with_context(|| format!("No preset named {}", name))?;
+            let (proxy_groups, _) = clash_api.get_proxies(&config.group_types).await?;
+            let report = presets::apply(&clash_api, preset, &proxy_groups).await;
+
+            for line in report.switched_lines() {
+                println!("{}", line);
+            }
+            for failure in &report.failed {
+                eprintln!("Failed: {}", failure);
+            }
+
+            let mut selections = Selections::load(&controller_config.name)
+                .unwrap_or_else(|_| Selections::empty(&controller_config.name));
+            if let Err(e) = selections.remember_all(&report.switched) {
+                warn!("Failed to remember selections: {}", e);
+            }
+
+            if !report.failed.is_empty() {
+                anyhow::bail!("{} of {} switches failed", report.failed.len(), preset.groups.len());
+            }
+            Ok(())
+        }
+        None => anyhow::bail!("Usage: clbar preset <name> | --save <name> | --list"),
+    }
+}
+
+/// `clbar check-config`: reports every config problem, then checks the
+/// configured groups against each reachable controller.
+pub async fn check_config(args: &[String], controller: Option<&str>) -> Result<()> {
+    if let Some(arg) = args.first() {
+        anyhow::bail!("Unknown argument for check-config: {}", arg);
+    }
+
+    let (layered, mut problems) = Config::load_with_problems()?;
+    let config = &layered.config;
+
+    let controllers = match controller {
+        Some(_) => vec![config.controller(controller)?],
+        None => config.controllers(),
+    };
+
+    for controller_config in &controllers {
+        let result = async {
+            let clash_api = ClashApi::new(controller_config)?;
+            clash_api.get_proxies(&config.group_types).await
+        }
+        .await;
+
+        match result {
+            Ok((proxy_groups, _)) => problems.extend(validate::validate_groups(
+                &layered,
+                &controller_config.name,
+                &proxy_groups,
+            )),
+            Err(e) => eprintln!("Skipping group checks for {}: {}", controller_config.name, e),
+        }
+    }
+
+    for problem in &problems {
+        println!("{}", problem);
+    }
+
+    let errors = problems.iter().filter(|problem| problem.is_error()).count();
+    let warnings = problems.len() - errors;
+    if errors > 0 {
+        anyhow::bail!("{} errors, {} warnings", errors, warnings);
+    }
+
+    println!("Config OK ({} warnings)", warnings);
+    Ok(())
+}
+
+/// `clbar config show`: prints every effective value and where it came from.
+pub fn config(args: &[String]) -> Result<()> {
+    match args.first().map(String::as_str) {
+        Some("show") => {}
+        _ => anyhow::bail!("Usage: clbar config show"),
+    }
+
+    let layered = Config::load_layered(None)?;
+    let mut effective = toml::Table::try_from(&layered.config).context("Failed to serialize config")?;
+    redact_secrets(&mut effective);
+
+    println!("# {}", Config::config_path()?.display());
+    print_values(&effective, "", &layered.sources);
+    Ok(())
+}
+
+fn print_values(table: &toml::Table, prefix: &str, sources: &IndexMap<String, String>) {
+    for (key, value) in table {
+        let path = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+        match value {
+            toml::Value::Table(table) => print_values(table, &path, sources),
+            value => {
+                let source = sources.get(&path).map(String::as_str).unwrap_or("default");
+                println!("{} = {}  # {}", path, value, source);
+            }
+        }
+    }
+}
+
+fn redact_secrets(table: &mut toml::Table) {
+    for (key, value) in table.iter_mut() {
+        match value {
+            toml::Value::String(secret) if (key == "secret" || key == "clash_secret") && !secret.is_empty() => {
+                *secret = "[redacted]".to_string();
+            }
+            toml::Value::Table(table) => redact_secrets(table),
+            toml::Value::Array(items) => {
+                for item in items {
+                    if let toml::Value::Table(table) = item {
+                        redact_secrets(table);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
@@ -1,7 +1,14 @@
 use anyhow::{Context, Result};
+use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use toml_edit::{DocumentMut, Item};
+use tracing::warn;
+
+use crate::layers::{self, Layered};
+use crate::validate::{self, Problem};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
@@ -11,9 +18,30 @@ pub struct Config {
     #[serde(default = "default_clash_secret")]
     pub clash_secret: String,
 
-    #[serde(default = "default_proxy_groups")]
+    #[serde(default)]
+    pub clash_secret_file: String,
+
+    #[serde(default)]
+    pub clash_secret_command: String,
+
+    #[serde(default = "default_clash_secret_env")]
+    pub clash_secret_env: String,
+
+    #[serde(default)]
+    pub clash_tls: TlsConfig,
+
+    #[serde(default)]
+    pub clash_proxy: String,
+
+    #[serde(default)]
     pub proxy_groups: Vec<String>,
 
+    #[serde(default = "default_group_types")]
+    pub group_types: Vec<String>,
+
+    #[serde(default)]
+    pub show_hidden_groups: bool,
+
     #[serde(default = "default_refresh_interval")]
     pub refresh_interval_secs: u64,
 
@@ -31,6 +59,229 @@ pub struct Config {
 
     #[serde(default = "default_delay_yellow_threshold")]
     pub delay_yellow_threshold: u32,
+
+    #[serde(default)]
+    pub show_node_badges: bool,
+
+    /// Tray tooltip; see config.toml.example for the placeholders
+    #[serde(default = "default_tooltip_template")]
+    pub tooltip_template: String,
+
+    #[serde(default)]
+    pub watchdog: WatchdogConfig,
+
+    #[serde(default)]
+    pub logs: LogsConfig,
+
+    #[serde(default)]
+    pub logging: LoggingConfig,
+
+    #[serde(default)]
+    pub hotkeys: HotkeysConfig,
+
+    #[serde(default)]
+    pub icon_actions: IconActionsConfig,
+
+    #[serde(default)]
+    pub notifications: NotificationsConfig,
+
+    #[serde(default)]
+    pub presets: Vec<Preset>,
+
+    #[serde(default)]
+    pub controllers: Vec<ControllerConfig>,
+}
+
+/// A named Clash external controller. When none are configured,
+/// `clash_api_url` and `clash_secret` form a single "default" controller.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ControllerConfig {
+    pub name: String,
+
+    pub url: String,
+
+    #[serde(default)]
+    pub secret: String,
+
+    /// File holding the secret; must not be readable by other users
+    #[serde(default)]
+    pub secret_file: String,
+
+    /// Command printing the secret, e.g. `pass show clash`
+    #[serde(default)]
+    pub secret_command: String,
+
+    /// Environment variable holding the secret; takes precedence when set
+    #[serde(default)]
+    pub secret_env: String,
+
+    #[serde(default)]
+    pub tls: TlsConfig,
+
+    /// HTTP or SOCKS5 proxy used to reach the controller
+    #[serde(default)]
+    pub proxy: String,
+}
+
+/// TLS options for an `https://` controller. Paths point to PEM files.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TlsConfig {
+    #[serde(default)]
+    pub ca_cert: String,
+
+    #[serde(default)]
+    pub client_cert: String,
+
+    #[serde(default)]
+    pub client_key: String,
+
+    /// SHA-256 fingerprint of the controller's certificate; when set, only
+    /// this certificate is accepted, whoever issued it
+    #[serde(default)]
+    pub fingerprint: String,
+
+    /// Skips certificate verification entirely
+    #[serde(default)]
+    pub insecure: bool,
+}
+
+/// A named set of selections; each group maps to a node name or a regex.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Preset {
+    pub name: String,
+    pub groups: IndexMap<String, String>,
+}
+
+/// Commands meant for Hyprland `bind = ...` lines; see `clbar hyprland-binds`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HotkeysConfig {
+    /// Group cycled and picked by default; empty = first shown Selector group
+    #[serde(default)]
+    pub group: String,
+
+    /// dmenu-style launcher reading node names on stdin
+    #[serde(default = "default_hotkeys_picker_command")]
+    pub picker_command: String,
+
+    #[serde(default = "default_hotkeys_notify")]
+    pub notify: bool,
+
+    /// Action to Hyprland key combination, e.g. `next = "SUPER ALT, N"`
+    #[serde(default = "default_hotkeys_binds")]
+    pub binds: IndexMap<String, String>,
+}
+
+/// Actions for clicks and scrolling on the tray icon, named like the
+/// `[hotkeys.binds]` actions plus `refresh`; empty does nothing.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IconActionsConfig {
+    #[serde(default = "default_icon_left_click")]
+    pub left_click: String,
+
+    #[serde(default = "default_icon_middle_click")]
+    pub middle_click: String,
+
+    #[serde(default = "default_icon_scroll_up")]
+    pub scroll_up: String,
+
+    #[serde(default = "default_icon_scroll_down")]
+    pub scroll_down: String,
+}
+
+/// Settings for each kind of tray notification.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NotificationsConfig {
+    #[serde(default = "default_notification")]
+    pub switch: NotificationConfig,
+
+    #[serde(default = "default_refresh_notification")]
+    pub refresh: NotificationConfig,
+
+    #[serde(default = "default_error_notification")]
+    pub error: NotificationConfig,
+
+    #[serde(default = "default_notification")]
+    pub reconnect: NotificationConfig,
+
+    #[serde(default = "default_notification")]
+    pub failover: NotificationConfig,
+
+    #[serde(default = "default_notification")]
+    pub action: NotificationConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NotificationConfig {
+    #[serde(default = "default_notification_enabled")]
+    pub enabled: bool,
+
+    #[serde(default = "default_notification_timeout")]
+    pub timeout_ms: u32,
+
+    /// low, normal or critical
+    #[serde(default = "default_notification_urgency")]
+    pub urgency: String,
+
+    /// Notifications of the same kind within this many seconds are dropped
+    #[serde(default)]
+    pub min_interval_secs: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogsConfig {
+    #[serde(default = "default_logs_level")]
+    pub level: String,
+
+    #[serde(default = "default_logs_buffer_size")]
+    pub buffer_size: usize,
+
+    #[serde(default = "default_logs_menu_lines")]
+    pub menu_lines: usize,
+
+    #[serde(default)]
+    pub notify_pattern: String,
+}
+
+/// clbar's own log, as opposed to the core's `[logs]`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoggingConfig {
+    /// Default filter when `RUST_LOG` is unset: error, warn, info, debug or trace
+    #[serde(default = "default_logging_level")]
+    pub level: String,
+
+    /// Also write to a daily rotated file under `$XDG_STATE_HOME/clbar/logs`
+    #[serde(default)]
+    pub file: bool,
+
+    #[serde(default = "default_logging_max_files")]
+    pub max_files: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WatchdogConfig {
+    #[serde(default)]
+    pub enabled: bool,
+
+    #[serde(default = "default_watchdog_groups")]
+    pub groups: Vec<String>,
+
+    #[serde(default = "default_watchdog_interval")]
+    pub interval_secs: u64,
+
+    #[serde(default = "default_watchdog_max_failures")]
+    pub max_failures: u32,
+
+    #[serde(default = "default_watchdog_max_delay")]
+    pub max_delay_ms: u32,
+
+    #[serde(default)]
+    pub preferred_nodes: Vec<String>,
+
+    #[serde(default)]
+    pub node_pattern: String,
+
+    #[serde(default = "default_watchdog_cooldown")]
+    pub cooldown_secs: u64,
 }
 
 fn default_clash_api_url() -> String {
@@ -41,8 +292,15 @@ fn default_clash_secret() -> String {
     String::new()
 }
 
-fn default_proxy_groups() -> Vec<String> {
-    vec!["PROXY".to_string()]
+fn default_clash_secret_env() -> String {
+    "CLBAR_SECRET".to_string()
+}
+
+fn default_group_types() -> Vec<String> {
+    ["Selector", "URLTest", "Fallback", "LoadBalance", "Relay", "Smart"]
+        .iter()
+        .map(|group_type| group_type.to_string())
+        .collect()
 }
 
 fn default_refresh_interval() -> u64 {
@@ -69,41 +327,424 @@ fn default_delay_yellow_threshold() -> u32 {
     400
 }
 
+fn default_watchdog_groups() -> Vec<String> {
+    vec!["PROXY".to_string()]
+}
+
+fn default_watchdog_interval() -> u64 {
+    60
+}
+
+fn default_watchdog_max_failures() -> u32 {
+    3
+}
+
+fn default_watchdog_max_delay() -> u32 {
+    1000
+}
+
+fn default_watchdog_cooldown() -> u64 {
+    300
+}
+
+fn default_logs_level() -> String {
+    "warning".to_string()
+}
+
+fn default_logging_level() -> String {
+    "info".to_string()
+}
+
+fn default_logging_max_files() -> usize {
+    7
+}
+
+fn default_logs_buffer_size() -> usize {
+    200
+}
+
+fn default_logs_menu_lines() -> usize {
+    5
+}
+
+fn default_tooltip_template() -> String {
+    "clbar · {controller} · {state}\n{mode} mode · {version} · {memory} · connected {connected_for}\n{groups}\nRefreshed {last_refresh}\n{error}"
+        .to_string()
+}
+
+fn default_hotkeys_picker_command() -> String {
+    "fuzzel --dmenu --prompt 'Node: '".to_string()
+}
+
+fn default_hotkeys_notify() -> bool {
+    true
+}
+
+fn default_hotkeys_binds() -> IndexMap<String, String> {
+    [
+        ("next", "SUPER ALT, bracketright"),
+        ("prev", "SUPER ALT, bracketleft"),
+        ("fastest", "SUPER ALT, F"),
+        ("toggle-mode", "SUPER ALT, M"),
+        ("pick", "SUPER ALT, P"),
+    ]
+    .iter()
+    .map(|(action, keys)| (action.to_string(), keys.to_string()))
+    .collect()
+}
+
+impl Default for HotkeysConfig {
+    fn default() -> Self {
+        HotkeysConfig {
+            group: String::new(),
+            picker_command: default_hotkeys_picker_command(),
+            notify: default_hotkeys_notify(),
+            binds: default_hotkeys_binds(),
+        }
+    }
+}
+
+fn default_icon_left_click() -> String {
+    "pick".to_string()
+}
+
+fn default_icon_middle_click() -> String {
+    "toggle-mode".to_string()
+}
+
+fn default_icon_scroll_up() -> String {
+    "prev".to_string()
+}
+
+fn default_icon_scroll_down() -> String {
+    "next".to_string()
+}
+
+impl Default for IconActionsConfig {
+    fn default() -> Self {
+        IconActionsConfig {
+            left_click: default_icon_left_click(),
+            middle_click: default_icon_middle_click(),
+            scroll_up: default_icon_scroll_up(),
+            scroll_down: default_icon_scroll_down(),
+        }
+    }
+}
+
+fn default_notification_enabled() -> bool {
+    true
+}
+
+fn default_notification_timeout() -> u32 {
+    3000
+}
+
+fn default_notification_urgency() -> String {
+    "normal".to_string()
+}
+
+fn default_notification() -> NotificationConfig {
+    NotificationConfig {
+        enabled: default_notification_enabled(),
+        timeout_ms: default_notification_timeout(),
+        urgency: default_notification_urgency(),
+        min_interval_secs: 0,
+    }
+}
+
+fn default_refresh_notification() -> NotificationConfig {
+    NotificationConfig {
+        min_interval_secs: 60,
+        ..default_notification()
+    }
+}
+
+fn default_error_notification() -> NotificationConfig {
+    NotificationConfig {
+        timeout_ms: 5000,
+        urgency: "critical".to_string(),
+        ..default_notification()
+    }
+}
+
+impl Default for NotificationsConfig {
+    fn default() -> Self {
+        NotificationsConfig {
+            switch: default_notification(),
+            refresh: default_refresh_notification(),
+            error: default_error_notification(),
+            reconnect: default_notification(),
+            failover: default_notification(),
+            action: default_notification(),
+        }
+    }
+}
+
+impl Default for LogsConfig {
+    fn default() -> Self {
+        LogsConfig {
+            level: default_logs_level(),
+            buffer_size: default_logs_buffer_size(),
+            menu_lines: default_logs_menu_lines(),
+            notify_pattern: String::new(),
+        }
+    }
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig {
+            level: default_logging_level(),
+            file: false,
+            max_files: default_logging_max_files(),
+        }
+    }
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        WatchdogConfig {
+            enabled: false,
+            groups: default_watchdog_groups(),
+            interval_secs: default_watchdog_interval(),
+            max_failures: default_watchdog_max_failures(),
+            max_delay_ms: default_watchdog_max_delay(),
+            preferred_nodes: Vec::new(),
+            node_pattern: String::new(),
+            cooldown_secs: default_watchdog_cooldown(),
+        }
+    }
+}
+
 impl Default for Config {
     fn default() -> Self {
         Config {
             clash_api_url: default_clash_api_url(),
             clash_secret: default_clash_secret(),
-            proxy_groups: default_proxy_groups(),
+            clash_secret_file: String::new(),
+            clash_secret_command: String::new(),
+            clash_secret_env: default_clash_secret_env(),
+            clash_tls: TlsConfig::default(),
+            clash_proxy: String::new(),
+            proxy_groups: Vec::new(),
+            group_types: default_group_types(),
+            show_hidden_groups: false,
             refresh_interval_secs: default_refresh_interval(),
             delay_test_group: default_delay_test_group(),
             delay_test_url: default_delay_test_url(),
             delay_test_timeout: default_delay_test_timeout(),
             delay_green_threshold: default_delay_green_threshold(),
             delay_yellow_threshold: default_delay_yellow_threshold(),
+            show_node_badges: false,
+            tooltip_template: default_tooltip_template(),
+            watchdog: WatchdogConfig::default(),
+            logs: LogsConfig::default(),
+            logging: LoggingConfig::default(),
+            hotkeys: HotkeysConfig::default(),
+            icon_actions: IconActionsConfig::default(),
+            notifications: NotificationsConfig::default(),
+            presets: Vec::new(),
+            controllers: Vec::new(),
+        }
+    }
+}
+
+pub const DEFAULT_CONTROLLER: &str = "default";
+
+/// Command-line options that affect where and how the config is loaded.
+#[derive(Debug, Default)]
+pub struct ConfigOptions {
+    pub path: Option<PathBuf>,
+    pub overrides: Vec<String>,
+}
+
+static OPTIONS: OnceLock<ConfigOptions> = OnceLock::new();
+
+/// Written on first run so the generated config keeps the explanatory comments.
+const CONFIG_TEMPLATE: &str = include_str!("../config.toml.example");
+
+/// Structural equality; `Display` alone leaves out sub-tables.
+fn same_item(a: &Item, b: &Item) -> bool {
+    if let (Some(a), Some(b)) = (a.as_table_like(), b.as_table_like()) {
+        return a.len() == b.len()
+            && b.iter().all(|(key, b_child)| a.get(key).is_some_and(|a_child| same_item(a_child, b_child)));
+    }
+
+    if let (Some(a), Some(b)) = (a.as_array_of_tables(), b.as_array_of_tables()) {
+        return a.len() == b.len()
+            && a.iter().zip(b.iter()).all(|(a, b)| same_item(&Item::Table(a.clone()), &Item::Table(b.clone())));
+    }
+
+    match (a.as_value(), b.as_value()) {
+        (Some(a), Some(b)) => a.to_string().trim() == b.to_string().trim(),
+        _ => false,
+    }
+}
+
+/// Applies the difference between `old` and `new` to `target`, descending into
+/// tables so untouched keys keep their comments, order and formatting.
+fn merge_item(target: &mut Item, old: Option<&Item>, new: &Item) {
+    if old.is_some_and(|old| same_item(old, new)) {
+        return;
+    }
+
+    if target.is_table_like() && new.is_table_like() {
+        // Nested tables have to become inline inside an inline table
+        let converted;
+        let new = match (target.is_value(), new.clone().into_value()) {
+            (true, Ok(value)) => {
+                converted = Item::Value(value);
+                &converted
+            }
+            _ => new,
+        };
+
+        let new_table = new.as_table_like().expect("checked above");
+        for (key, new_child) in new_table.iter() {
+            let old_child = old.and_then(|old| old.get(key));
+            if old_child.is_some_and(|old_child| same_item(old_child, new_child)) {
+                continue;
+            }
+            merge_item(&mut target[key], old_child, new_child);
+        }
+
+        let removed: Vec<String> = target
+            .as_table_like()
+            .expect("checked above")
+            .iter()
+            .map(|(key, _)| key.to_string())
+            .filter(|key| !new_table.contains_key(key) && old.is_some_and(|old| old.get(key).is_some()))
+            .collect();
+        if let Some(table) = target.as_table_like_mut() {
+            for key in removed {
+                table.remove(&key);
+            }
+        }
+        return;
+    }
+
+    if let (Some(target_array), Some(new_array)) = (target.as_array_of_tables_mut(), new.as_array_of_tables()) {
+        let old_array = old.and_then(Item::as_array_of_tables);
+        while target_array.len() > new_array.len() {
+            target_array.remove(target_array.len() - 1);
         }
+
+        for (index, new_table) in new_array.iter().enumerate() {
+            let old_table = old_array.and_then(|old| old.get(index)).cloned().map(Item::Table);
+            match target_array.get_mut(index) {
+                Some(target_table) => {
+                    let mut target_item = Item::Table(std::mem::take(target_table));
+                    merge_item(&mut target_item, old_table.as_ref(), &Item::Table(new_table.clone()));
+                    if let Item::Table(table) = target_item {
+                        *target_table = table;
+                    }
+                }
+                None => target_array.push(new_table.clone()),
+            }
+        }
+        return;
+    }
+
+    // Keep the comment and spacing around a replaced value
+    match (target.as_value(), new.as_value()) {
+        (Some(existing), Some(value)) => {
+            let decor = existing.decor().clone();
+            let mut value = value.clone();
+            *value.decor_mut() = decor;
+            *target = Item::Value(value);
+        }
+        _ => *target = new.clone(),
     }
 }
 
 impl Config {
+    /// All configured controllers, falling back to the top-level API settings.
+    pub fn controllers(&self) -> Vec<ControllerConfig> {
+        if !self.controllers.is_empty() {
+            return self.controllers.clone();
+        }
+
+        vec![ControllerConfig {
+            name: DEFAULT_CONTROLLER.to_string(),
+            url: self.clash_api_url.clone(),
+            secret: self.clash_secret.clone(),
+            secret_file: self.clash_secret_file.clone(),
+            secret_command: self.clash_secret_command.clone(),
+            secret_env: self.clash_secret_env.clone(),
+            tls: self.clash_tls.clone(),
+            proxy: self.clash_proxy.clone(),
+        }]
+    }
+
+    /// The controller with the given name, or the first one when `name` is None.
+    pub fn controller(&self, name: Option<&str>) -> Result<ControllerConfig> {
+        let controllers = self.controllers();
+
+        match name {
+            Some(name) => controllers
+                .into_iter()
+                .find(|controller| controller.name == name)
+                .with_context(|| format!("No controller named {}", name)),
+            None => controllers
+                .into_iter()
+                .next()
+                .context("No controller configured"),
+        }
+    }
+
+    /// Loads the config, failing on validation errors. Warnings are printed.
     pub fn load() -> Result<Self> {
+        let (config, warnings) = Self::load_checked()?;
+        for warning in &warnings {
+            warn!("Config {}", warning);
+        }
+        Ok(config)
+    }
+
+    /// Loads the config, failing on validation errors and returning warnings.
+    pub fn load_checked() -> Result<(Self, Vec<Problem>)> {
+        let (layered, problems) = Self::load_with_problems()?;
+        let (errors, warnings): (Vec<Problem>, Vec<Problem>) =
+            problems.into_iter().partition(Problem::is_error);
+
+        if !errors.is_empty() {
+            let lines: Vec<String> = errors.iter().map(ToString::to_string).collect();
+            anyhow::bail!("Invalid config:\n{}", lines.join("\n"));
+        }
+
+        Ok((layered.config, warnings))
+    }
+
+    /// Loads the layered config and collects every validation problem, each
+    /// pointing at the layer its value came from.
+    pub fn load_with_problems() -> Result<(Layered, Vec<Problem>)> {
         let config_path = Self::config_path()?;
 
-        if !config_path.exists() {
-            let default_config = Config::default();
-            default_config.save()?;
-            return Ok(default_config);
+        // First run: create the user config unless a system-wide one provides the settings
+        if !config_path.exists() && !Path::new(layers::SYSTEM_CONFIG).exists() {
+            Config::default().save()?;
         }
 
-        let content = fs::read_to_string(&config_path)
-            .context("Failed to read config file")?;
+        let layered = Self::load_layered(None)?;
+        let problems = validate::validate(&layered.config, Some(&layered));
+        Ok((layered, problems))
+    }
 
-        let config: Config = toml::from_str(&content)
-            .context("Failed to parse config file")?;
+    /// The effective config with the source of each value, reading the user
+    /// config from `user_content` when given.
+    pub fn load_layered(user_content: Option<&str>) -> Result<Layered> {
+        let options = OPTIONS.get();
+        let overrides = options.map(|options| options.overrides.as_slice()).unwrap_or_default();
+        layers::load(&Self::config_path()?, user_content, overrides)
+    }
 
-        Ok(config)
+    /// Sets the `--config` path and `--set` overrides; call once before loading.
+    pub fn set_options(options: ConfigOptions) {
+        OPTIONS.set(options).ok();
     }
 
+    /// Writes the config, updating only the keys that changed so comments and
+    /// formatting survive. A missing file starts from the commented example.
     pub fn save(&self) -> Result<()> {
         let config_path = Self::config_path()?;
 
@@ -112,19 +753,171 @@ impl Config {
                 .context("Failed to create config directory")?;
         }
 
-        let content = toml::to_string_pretty(self)
-            .context("Failed to serialize config")?;
+        let content = if config_path.exists() {
+            fs::read_to_string(&config_path)
+                .context("Failed to read config file")?
+        } else {
+            CONFIG_TEMPLATE.to_string()
+        };
+
+        let mut document: DocumentMut = content
+            .parse()
+            .context("Failed to parse config file")?;
+
+        // Diff against the effective config so values from other layers
+        // (system config, environment, --set) are not copied into this file
+        let effective = Self::load_layered(Some(&content))?.config;
+
+        let old = Self::to_document(&effective)?;
+        let new = Self::to_document(self)?;
+        merge_item(document.as_item_mut(), Some(old.as_item()), new.as_item());
 
-        fs::write(&config_path, content)
+        fs::write(&config_path, document.to_string())
             .context("Failed to write config file")?;
 
         Ok(())
     }
 
-    fn config_path() -> Result<PathBuf> {
+    fn to_document(config: &Config) -> Result<DocumentMut> {
+        toml::to_string(config)
+            .context("Failed to serialize config")?
+            .parse()
+            .context("Failed to serialize config")
+    }
+
+    /// `--config`, then `$CLBAR_CONFIG`, then `~/.config/clbar/config.toml`.
+    pub fn config_path() -> Result<PathBuf> {
+        if let Some(path) = OPTIONS.get().and_then(|options| options.path.clone()) {
+            return Ok(path);
+        }
+
+        if let Some(path) = std::env::var_os("CLBAR_CONFIG").filter(|path| !path.is_empty()) {
+            return Ok(PathBuf::from(path));
+        }
+
         let config_dir = dirs::config_dir()
             .context("Failed to get config directory")?;
 
         Ok(config_dir.join("clbar").join("config.toml"))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What `save` writes over `content` when the effective config changes from `old` to `new`.
+    fn save_over(content: &str, old: &Config, new: &Config) -> String {
+        let mut document: DocumentMut = content.parse().unwrap();
+        let old = Config::to_document(old).unwrap();
+        let new = Config::to_document(new).unwrap();
+        merge_item(document.as_item_mut(), Some(old.as_item()), new.as_item());
+        document.to_string()
+    }
+
+    fn parse(content: &str) -> Config {
+        toml::from_str(content).unwrap()
+    }
+
+    #[test]
+    fn saving_the_defaults_leaves_the_template_unchanged() {
+        // What a first run writes: `Config::default().save()` over the template
+        let effective = parse(CONFIG_TEMPLATE);
+        assert_eq!(save_over(CONFIG_TEMPLATE, &effective, &Config::default()), CONFIG_TEMPLATE);
+        assert_eq!(
+            toml::to_string(&effective).unwrap(),
+            toml::to_string(&Config::default()).unwrap()
+        );
+    }
+
+    #[test]
+    fn unchanged_config_leaves_the_file_alone() {
+        let content = "# My settings\nrefresh_interval_secs = 5 # fast\n\n[watchdog]\nenabled = true\n";
+        let config = parse(content);
+        assert_eq!(save_over(content, &config, &config), content);
+    }
+
+    #[test]
+    fn changed_values_keep_their_comments_and_neighbours() {
+        let content = "# My settings\nrefresh_interval_secs = 5 # fast\ndelay_test_timeout = 3000\n";
+        let old = parse(content);
+        let new = Config {
+            refresh_interval_secs: 10,
+            ..old.clone()
+        };
+
+        assert_eq!(
+            save_over(content, &old, &new),
+            "# My settings\nrefresh_interval_secs = 10 # fast\ndelay_test_timeout = 3000\n"
+        );
+    }
+
+    #[test]
+    fn values_from_other_layers_are_not_copied_into_the_file() {
+        let content = "refresh_interval_secs = 5\n";
+        // e.g. delay_test_timeout came from $CLBAR_DELAY_TEST_TIMEOUT
+        let old = Config {
+            delay_test_timeout: 9000,
+            ..parse(content)
+        };
+        let new = Config {
+            refresh_interval_secs: 10,
+            ..old.clone()
+        };
+
+        assert_eq!(save_over(content, &old, &new), "refresh_interval_secs = 10\n");
+    }
+
+    #[test]
+    fn removed_keys_are_dropped_from_tables() {
+        let content = "[hotkeys.binds]\nnext = \"SUPER, N\" # cycle\nprev = \"SUPER, P\"\n";
+        let old = parse(content);
+        let mut new = old.clone();
+        new.hotkeys.binds.shift_remove("prev");
+
+        assert_eq!(save_over(content, &old, &new), "[hotkeys.binds]\nnext = \"SUPER, N\" # cycle\n");
+    }
+
+    #[test]
+    fn inline_tables_stay_inline() {
+        let content = "[hotkeys]\nbinds = { next = \"SUPER, N\" }\n";
+        let old = parse(content);
+        let mut new = old.clone();
+        new.hotkeys.binds.insert("prev".to_string(), "SUPER, P".to_string());
+
+        let saved = save_over(content, &old, &new);
+        assert!(saved.starts_with("[hotkeys]\nbinds = { next = \"SUPER, N\""), "{}", saved);
+        assert_eq!(saved.lines().count(), 2, "{}", saved);
+        assert_eq!(parse(&saved).hotkeys.binds, new.hotkeys.binds);
+    }
+
+    #[test]
+    fn controllers_are_edited_in_place_and_trimmed() {
+        let content = "[[controllers]]\nname = \"home\" # main box\nurl = \"http://127.0.0.1:9090\"\n\n\
+                       [[controllers]]\nname = \"router\"\nurl = \"http://192.168.1.1:9090\"\n";
+        let old = parse(content);
+        let mut new = old.clone();
+        new.controllers[0].url = "http://127.0.0.1:9091".to_string();
+        new.controllers.truncate(1);
+
+        assert_eq!(
+            save_over(content, &old, &new),
+            "[[controllers]]\nname = \"home\" # main box\nurl = \"http://127.0.0.1:9091\"\n"
+        );
+    }
+
+    #[test]
+    fn new_controllers_are_appended() {
+        let content = "[[controllers]]\nname = \"home\"\nurl = \"http://127.0.0.1:9090\"\n";
+        let old = parse(content);
+        let mut new = old.clone();
+        let mut router = new.controllers[0].clone();
+        router.name = "router".to_string();
+        new.controllers.push(router);
+
+        let saved = save_over(content, &old, &new);
+        assert!(saved.starts_with(content), "{}", saved);
+        assert_eq!(parse(&saved).controllers.len(), 2);
+        assert_eq!(parse(&saved).controllers[1].name, "router");
+    }
+}
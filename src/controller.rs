@@ -0,0 +1,338 @@
+use anyhow::{Context, Result};
+use indexmap::IndexMap;
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::clash_api::ClashApi;
+use crate::config::{Config, ControllerConfig};
+use crate::models::{CoreFlavour, CoreStatus, CoreVersion, GroupType, NodeInfo, ProxyGroup, SwitchReport};
+use crate::state::Selections;
+use crate::watchdog::Watchdog;
+
+/// What a refresh found out about the core.
+pub enum Refreshed {
+    Unchanged,
+    /// The core answers again; remembered selections were restored
+    Reconnected(SwitchReport),
+    /// Every Selector moved back to its first member, as after a restart or
+    /// config reload, away from remembered nodes
+    SelectorsReset,
+}
+
+/// One Clash external controller with its own groups, connection state,
+/// remembered selections and watchdog.
+pub struct Controller {
+    pub name: String,
+    pub clash_api: ClashApi,
+    pub proxy_groups: IndexMap<String, ProxyGroup>,
+    pub nodes: IndexMap<String, NodeInfo>,
+    pub core_status: CoreStatus,
+    pub selections: Selections,
+    /// Taken while a check runs in the background
+    pub watchdog: Option<Watchdog>,
+    /// A periodic refresh is running in the background
+    pub polling: bool,
+    /// When groups were last fetched successfully
+    pub last_refresh: Option<Instant>,
+    /// Why the last fetch failed, cleared by the next success
+    pub last_error: Option<String>,
+}
+
+impl Controller {
+    pub fn new(controller_config: &ControllerConfig, config: &Config) -> Result<Self> {
+        let clash_api = ClashApi::new(controller_config)
+            .with_context(|| format!("Failed to create Clash API client for {}", controller_config.name))?;
+
+        let selections = Selections::load(&controller_config.name).unwrap_or_else(|e| {
+            warn!(controller = %controller_config.name, "Failed to load remembered selections: {}", e);
+            Selections::empty(&controller_config.name)
+        });
+
+        let watchdog = Watchdog::new(&config.watchdog).context("Failed to create watchdog")?;
+
+        Ok(Controller {
+            name: controller_config.name.clone(),
+            clash_api,
+            proxy_groups: IndexMap::new(),
+            nodes: IndexMap::new(),
+            core_status: CoreStatus {
+                version: None,
+                memory: None,
+                connected_since: None,
+            },
+            selections,
+            watchdog: Some(watchdog),
+            polling: false,
+            last_refresh: None,
+            last_error: None,
+        })
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.core_status.connected_since.is_some()
+    }
+
+    pub fn flavour(&self) -> Option<CoreFlavour> {
+        self.core_status.version.as_ref().map(|version| version.flavour)
+    }
+
+    pub fn supports_pinning(&self) -> bool {
+        self.flavour().is_some_and(|flavour| flavour.supports_pinning())
+    }
+
+    /// Connects for the first time, detecting the core flavour.
+    pub async fn connect(&mut self, config: &Config) -> Result<()> {
+        self.clash_api.test_connection().await?;
+        self.detect_version().await;
+        self.fetch_groups(config).await?;
+        self.core_status.connected_since = Some(Instant::now());
+        Ok(())
+    }
+
+    /// Re-fetches groups and tracks the connection state. When the core comes
+    /// back after being unreachable, its flavour is re-detected and remembered
+    /// selections are restored.
+    pub async fn refresh(&mut self, config: &Config) -> Result<Refreshed> {
+        let reconnected = !self.is_connected();
+        let previous_groups = self.proxy_groups.clone();
+
+        if let Err(e) = self.fetch_groups(config).await {
+            self.core_status.connected_since = None;
+            self.core_status.memory = None;
+            return Err(e);
+        }
+
+        if reconnected {
+            self.core_status.connected_since = Some(Instant::now());
+            self.detect_version().await;
+            return Ok(Refreshed::Reconnected(self.restore_selections(config).await));
+        }
+
+        Ok(self.check_reset(&previous_groups))
+    }
+
+    /// A restart or config reload quicker than the refresh interval never
+    /// shows up as a failed fetch, only as Selectors back on their defaults.
+    /// Picking those nodes by hand looks the same, so this only reports it.
+    fn check_reset(&mut self, previous_groups: &IndexMap<String, ProxyGroup>) -> Refreshed {
+        if !selectors_reset(previous_groups, &self.proxy_groups) {
+            return Refreshed::Unchanged;
+        }
+
+        // `clbar next` and friends remember their switches in the file
+        match Selections::load(&self.name) {
+            Ok(selections) => self.selections = selections,
+            Err(e) => warn!(controller = %self.name, "Failed to reload remembered selections: {}", e),
+        }
+        let differs = self.selections.groups.iter().any(|(group, node)| {
+            self.proxy_groups
+                .get(group)
+                .is_some_and(|group| group.now.as_ref() != Some(node))
+        });
+        if !differs {
+            return Refreshed::Unchanged;
+        }
+
+        info!(controller = %self.name, "Selector groups are back on their first nodes");
+        Refreshed::SelectorsReset
+    }
+
+    /// Picks up the groups of a core that came back after `wait_for_core`,
+    /// then restores remembered selections.
+    pub async fn resume(&mut self, config: &Config) -> Result<SwitchReport> {
+        if let Err(e) = self.fetch_groups(config).await {
+            self.core_status.connected_since = None;
+            return Err(e);
+        }
+
+        self.core_status.connected_since.get_or_insert_with(Instant::now);
+        Ok(self.restore_selections(config).await)
+    }
+
+    pub async fn fetch_groups(&mut self, config: &Config) -> Result<()> {
+        let (all_groups, nodes) = match self.clash_api.get_proxies(&config.group_types).await {
+            Ok(fetched) => fetched,
+            Err(e) => {
+                self.last_error = Some(format!("{:#}", e));
+                return Err(e);
+            }
+        };
+
+        self.set_groups(config, all_groups, nodes);
+        Ok(())
+    }
+
+    /// Starts a periodic refresh: a delay test, then the groups. A core that
+    /// answers again after being unreachable has its flavour re-detected and
+    /// remembered selections restored. The future only holds copies, so it can
+    /// run in a spawned task; hand its result to `apply_poll`.
+    pub fn poll(&self, config: &Config) -> impl Future<Output = Poll> + Send + 'static {
+        let name = self.name.clone();
+        let mut clash_api = self.clash_api.clone();
+        let selections = self.selections.clone();
+        let connected = self.is_connected();
+        let config = config.clone();
+
+        async move {
+            if connected {
+                if let Err(e) = clash_api
+                    .trigger_delay_test(&config.delay_test_group, &config.delay_test_url, config.delay_test_timeout)
+                    .await
+                {
+                    warn!(controller = %name, "Auto-refresh delay test failed (continuing anyway): {}", e);
+                }
+            }
+
+            let mut poll = Poll {
+                groups: clash_api.get_proxies(&config.group_types).await,
+                reconnected: None,
+            };
+            let Ok((ref all_groups, _)) = poll.groups else {
+                return poll;
+            };
+            if connected {
+                return poll;
+            }
+
+            let version = clash_api.version().await;
+            if let Ok(ref version) = version {
+                clash_api.set_flavour(version.flavour);
+            }
+            let report = selections
+                .restore(&clash_api, &shown_groups(&config, all_groups.clone()))
+                .await;
+            if !report.switched.is_empty() {
+                if let Ok(groups) = clash_api.get_proxies(&config.group_types).await {
+                    poll.groups = Ok(groups);
+                }
+            }
+            poll.reconnected = Some((version, report));
+            poll
+        }
+    }
+
+    /// Takes over the result of `poll`, like `refresh` does for its own fetch.
+    pub fn apply_poll(&mut self, config: &Config, poll: Poll) -> Result<Refreshed> {
+        let previous_groups = self.proxy_groups.clone();
+
+        let (all_groups, nodes) = match poll.groups {
+            Ok(fetched) => fetched,
+            Err(e) => {
+                self.last_error = Some(format!("{:#}", e));
+                self.core_status.connected_since = None;
+                self.core_status.memory = None;
+                return Err(e);
+            }
+        };
+        self.set_groups(config, all_groups, nodes);
+
+        if let Some((version, report)) = poll.reconnected {
+            self.core_status.connected_since = Some(Instant::now());
+            self.set_version(version);
+            return Ok(Refreshed::Reconnected(report));
+        }
+
+        // Marked down while the poll ran, e.g. by a failed restart
+        self.core_status.connected_since.get_or_insert_with(Instant::now);
+        Ok(self.check_reset(&previous_groups))
+    }
+
+    fn set_groups(
+        &mut self,
+        config: &Config,
+        all_groups: IndexMap<String, ProxyGroup>,
+        nodes: IndexMap<String, NodeInfo>,
+    ) {
+        self.proxy_groups = shown_groups(config, all_groups);
+        self.nodes = nodes;
+        self.last_refresh = Some(Instant::now());
+        self.last_error = None;
+    }
+
+    /// Switches groups back to their remembered nodes.
+    pub async fn restore_selections(&mut self, config: &Config) -> SwitchReport {
+        let report = self.selections.restore(&self.clash_api, &self.proxy_groups).await;
+
+        if !report.switched.is_empty() {
+            self.fetch_groups(config).await.ok();
+        }
+
+        report
+    }
+
+    async fn detect_version(&mut self) {
+        let version = self.clash_api.version().await;
+        self.set_version(version);
+    }
+
+    fn set_version(&mut self, version: Result<CoreVersion>) {
+        match version {
+            Ok(version) => {
+                info!(controller = %self.name, "Clash core {} {}", version.flavour.name(), version.version);
+                self.clash_api.set_flavour(version.flavour);
+                self.core_status.version = Some(version);
+            }
+            Err(e) => warn!(controller = %self.name, "Failed to detect Clash core version: {}", e),
+        }
+    }
+}
+
+/// Groups fetched in the background by `Controller::poll`.
+pub struct Poll {
+    groups: Result<(IndexMap<String, ProxyGroup>, IndexMap<String, NodeInfo>)>,
+    /// The core's version and the restore, when it answered again after being unreachable
+    reconnected: Option<(Result<CoreVersion>, SwitchReport)>,
+}
+
+impl Poll {
+    pub fn is_reachable(&self) -> bool {
+        self.groups.is_ok()
+    }
+}
+
+/// The configured groups, without hidden ones unless asked for.
+fn shown_groups(config: &Config, all_groups: IndexMap<String, ProxyGroup>) -> IndexMap<String, ProxyGroup> {
+    all_groups
+        .into_iter()
+        .filter(|(name, _)| config.proxy_groups.is_empty() || config.proxy_groups.contains(name))
+        .filter(|(_, group)| config.show_hidden_groups || !group.hidden)
+        .collect()
+}
+
+/// Retries fetching groups for a few seconds while the core restarts. Only
+/// needs a copy of the API client, so it can run in a spawned task.
+pub async fn wait_for_core(clash_api: ClashApi, group_types: Vec<String>) -> Result<()> {
+    let mut attempts = 0;
+    loop {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        match clash_api.get_proxies(&group_types).await {
+            Ok(_) => return Ok(()),
+            Err(e) if attempts >= 10 => return Err(e),
+            Err(_) => attempts += 1,
+        }
+    }
+}
+
+/// Selector groups go back to their first member when the core restarts or
+/// reloads its config: true when every Selector that was on another node
+/// moved there at once. With a single group that is also what picking its
+/// first node looks like, so callers must not act on this alone.
+fn selectors_reset(previous: &IndexMap<String, ProxyGroup>, current: &IndexMap<String, ProxyGroup>) -> bool {
+    let first_member = |group: &ProxyGroup| group.all.as_ref().and_then(|all| all.first()).cloned();
+
+    let mut reset = 0;
+    for (name, before) in previous {
+        if before.group_type != GroupType::Selector || before.now == first_member(before) {
+            continue;
+        }
+        let Some(after) = current.get(name) else {
+            continue;
+        };
+        if after.now != first_member(after) {
+            return false;
+        }
+        reset += 1;
+    }
+    reset > 0
+}
@@ -0,0 +1,378 @@
+use anyhow::{Context, Result};
+use indexmap::IndexMap;
+use std::process::Stdio;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::task::JoinSet;
+use tracing::warn;
+
+use crate::clash_api::ClashApi;
+use crate::cli;
+use crate::config::Config;
+use crate::models::{CoreConfigPatch, GroupType, ProxyGroup, BUILTIN_NODES};
+use crate::notifications::{NotificationKind, Notifier};
+use crate::state::Selections;
+
+/// Result of a hotkey command: a message for the notification and the
+/// switch to remember, if any.
+struct Outcome {
+    message: String,
+    switched: Option<(String, String)>,
+}
+
+/// `clbar next|prev|fastest|pick [group]` and `clbar mode <toggle|rule|global|direct>`.
+/// Built for Hyprland `bind = ...` lines, so results are shown as notifications.
+pub async fn run(action: &str, args: &[String], controller: Option<&str>) -> Result<()> {
+    let (config, clash_api, controller_config) = match cli::connect(controller).await {
+        Ok(connection) => connection,
+        Err(e) => {
+            // The config may be what failed; fall back to the default settings
+            let notifications = Config::load_with_problems()
+                .map(|(layered, _)| layered.config.notifications)
+                .unwrap_or_default();
+            Notifier::new(&notifications).show(NotificationKind::Error, "clbar", &format!("{:#}", e));
+            return Err(e);
+        }
+    };
+    // `[notifications.*]` applies as in the tray; `hotkeys.notify` silences everything
+    let mut notifier = config.hotkeys.notify.then(|| Notifier::new(&config.notifications));
+
+    let result = match action {
+        "next" => cycle(&config, &clash_api, args.first(), 1).await,
+        "prev" => cycle(&config, &clash_api, args.first(), -1).await,
+        "fastest" => fastest(&config, &clash_api, args.first()).await,
+        "pick" => pick(&config, &clash_api, args.first()).await,
+        "mode" => mode(&clash_api, args.first().map(String::as_str).unwrap_or("toggle")).await,
+        other => anyhow::bail!("Unknown hotkey action: {}", other),
+    };
+
+    match result {
+        Ok(outcome) => {
+            println!("{}", outcome.message);
+            if let Some(ref mut notifier) = notifier {
+                notifier.show(NotificationKind::Switch, "clbar", &outcome.message);
+            }
+
+            if let Some(switched) = outcome.switched {
+                let mut selections = Selections::load(&controller_config.name)
+                    .unwrap_or_else(|_| Selections::empty(&controller_config.name));
+                if let Err(e) = selections.remember_all(&[switched]) {
+                    warn!("Failed to remember selection: {}", e);
+                }
+            }
+            Ok(())
+        }
+        Err(e) => {
+            if let Some(ref mut notifier) = notifier {
+                notifier.show(NotificationKind::Error, "clbar", &format!("{:#}", e));
+            }
+            Err(e)
+        }
+    }
+}
+
+/// `clbar hyprland-binds`: prints bind lines for the actions in `[hotkeys.binds]`.
+pub fn hyprland_binds(args: &[String], controller: Option<&str>) -> Result<()> {
+    if let Some(arg) = args.first() {
+        anyhow::bail!("Unknown argument for hyprland-binds: {}", arg);
+    }
+
+    let config = Config::load().context("Failed to load configuration")?;
+    let program = match controller {
+        Some(name) => format!("clbar --controller {}", shell_quote(name)),
+        None => "clbar".to_string(),
+    };
+
+    println!("# clbar hotkeys, generated by `clbar hyprland-binds`");
+    for (action, keys) in &config.hotkeys.binds {
+        match bind_command(action) {
+            Some(command) => println!("bind = {}, exec, {} {}", keys, program, command),
+            None => println!("# unknown action {} (bound to {})", action, keys),
+        }
+    }
+    Ok(())
+}
+
+/// The clbar arguments for a `[hotkeys.binds]` action, quoted for the shell
+/// that Hyprland runs `exec` lines with.
+pub fn bind_command(action: &str) -> Option<String> {
+    match action {
+        "next" | "prev" | "fastest" | "pick" => Some(action.to_string()),
+        "toggle-mode" => Some("mode toggle".to_string()),
+        _ => {
+            if let Some(mode) = action.strip_prefix("mode:") {
+                Some(format!("mode {}", shell_quote(mode)))
+            } else {
+                action.strip_prefix("preset:").map(|preset| format!("preset {}", shell_quote(preset)))
+            }
+        }
+    }
+}
+
+/// Single-quotes `arg` unless it only has characters the shell leaves alone.
+fn shell_quote(arg: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "-_./:@%+=,".contains(c);
+    if !arg.is_empty() && arg.chars().all(plain) {
+        return arg.to_string();
+    }
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+async fn cycle(config: &Config, clash_api: &ClashApi, group: Option<&String>, step: isize) -> Result<Outcome> {
+    let (proxy_groups, _) = clash_api.get_proxies(&config.group_types).await?;
+    let (group_name, node) = step_node(config, &proxy_groups, group, step)?;
+
+    switch(clash_api, &group_name, &node).await
+}
+
+/// The target group and the node `step` places away from its current one, wrapping around.
+pub fn step_node(
+    config: &Config,
+    proxy_groups: &IndexMap<String, ProxyGroup>,
+    group: Option<&String>,
+    step: isize,
+) -> Result<(String, String)> {
+    let (group_name, group) = target_group(config, proxy_groups, group)?;
+    let members = group.all.as_deref().unwrap_or_default();
+    if members.is_empty() {
+        anyhow::bail!("{} has no nodes", group_name);
+    }
+
+    let current = group
+        .now
+        .as_ref()
+        .and_then(|now| members.iter().position(|member| member == now))
+        .unwrap_or(0) as isize;
+    let next = members[(current + step).rem_euclid(members.len() as isize) as usize].clone();
+
+    Ok((group_name, next))
+}
+
+async fn fastest(config: &Config, clash_api: &ClashApi, group: Option<&String>) -> Result<Outcome> {
+    let (proxy_groups, _) = clash_api.get_proxies(&config.group_types).await?;
+    let (group_name, node, delay) = fastest_node(config, clash_api, &proxy_groups, group).await?;
+
+    let mut outcome = switch(clash_api, &group_name, &node).await?;
+    outcome.message = format!("{} ({} ms)", outcome.message, delay);
+    Ok(outcome)
+}
+
+/// The target group and its lowest-delay node, tested in parallel.
+pub async fn fastest_node(
+    config: &Config,
+    clash_api: &ClashApi,
+    proxy_groups: &IndexMap<String, ProxyGroup>,
+    group: Option<&String>,
+) -> Result<(String, String, u32)> {
+    let (group_name, group) = target_group(config, proxy_groups, group)?;
+
+    let mut tasks = JoinSet::new();
+    for node in group.all.iter().flatten() {
+        if BUILTIN_NODES.contains(&node.as_str()) {
+            continue;
+        }
+        let clash_api = clash_api.clone();
+        let node = node.clone();
+        let url = config.delay_test_url.clone();
+        let timeout = config.delay_test_timeout;
+        tasks.spawn(async move {
+            let delay = clash_api.test_node_delay(&node, &url, timeout).await;
+            (node, delay)
+        });
+    }
+
+    let mut best: Option<(String, u32)> = None;
+    while let Some(joined) = tasks.join_next().await {
+        if let Ok((node, Ok(delay))) = joined {
+            if best.as_ref().is_none_or(|(_, best_delay)| delay < *best_delay) {
+                best = Some((node, delay));
+            }
+        }
+    }
+
+    let (node, delay) = best.with_context(|| format!("No node in {} answered the delay test", group_name))?;
+    Ok((group_name, node, delay))
+}
+
+async fn pick(config: &Config, clash_api: &ClashApi, group: Option<&String>) -> Result<Outcome> {
+    let (proxy_groups, _) = clash_api.get_proxies(&config.group_types).await?;
+    match pick_node(config, &proxy_groups, group).await? {
+        Some((group_name, node)) => switch(clash_api, &group_name, &node).await,
+        None => Ok(Outcome {
+            message: "No node picked".to_string(),
+            switched: None,
+        }),
+    }
+}
+
+/// Runs `picker_command` with the target group's nodes; None if nothing was picked.
+pub async fn pick_node(
+    config: &Config,
+    proxy_groups: &IndexMap<String, ProxyGroup>,
+    group: Option<&String>,
+) -> Result<Option<(String, String)>> {
+    let (group_name, group) = target_group(config, proxy_groups, group)?;
+    let members = group.all.clone().unwrap_or_default();
+
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(&config.hotkeys.picker_command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to run picker: {}", config.hotkeys.picker_command))?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(members.join("\n").as_bytes()).await.ok();
+    }
+
+    let output = child.wait_with_output().await.context("Picker failed")?;
+    let picked = String::from_utf8_lossy(&output.stdout).trim().to_string();
+
+    if picked.is_empty() {
+        return Ok(None);
+    }
+    if !members.contains(&picked) {
+        anyhow::bail!("{} is not a node of {}", picked, group_name);
+    }
+
+    Ok(Some((group_name, picked)))
+}
+
+async fn mode(clash_api: &ClashApi, mode: &str) -> Result<Outcome> {
+    let mode = match mode {
+        "toggle" => {
+            let current = clash_api.get_configs().await?.mode;
+            if current.eq_ignore_ascii_case("global") { "rule" } else { "global" }
+        }
+        "rule" | "global" | "direct" => mode,
+        other => anyhow::bail!("Unknown mode {} (expected toggle, rule, global or direct)", other),
+    };
+
+    clash_api
+        .patch_configs(&CoreConfigPatch {
+            mode: Some(mode.to_string()),
+            ..CoreConfigPatch::default()
+        })
+        .await?;
+
+    Ok(Outcome {
+        message: format!("Mode: {}", mode),
+        switched: None,
+    })
+}
+
+async fn switch(clash_api: &ClashApi, group: &str, node: &str) -> Result<Outcome> {
+    clash_api.switch_node(group, node).await?;
+
+    Ok(Outcome {
+        message: format!("{} → {}", group, node),
+        switched: Some((group.to_string(), node.to_string())),
+    })
+}
+
+/// The named group, else `hotkeys.group`, else the first shown Selector group.
+fn target_group(
+    config: &Config,
+    proxy_groups: &IndexMap<String, ProxyGroup>,
+    group: Option<&String>,
+) -> Result<(String, ProxyGroup)> {
+    let name = group
+        .cloned()
+        .or_else(|| Some(config.hotkeys.group.clone()).filter(|name| !name.is_empty()))
+        .or_else(|| {
+            proxy_groups
+                .iter()
+                .filter(|(name, _)| config.proxy_groups.is_empty() || config.proxy_groups.contains(name))
+                .find(|(_, group)| group.group_type == GroupType::Selector && !group.hidden)
+                .map(|(name, _)| name.clone())
+        })
+        .context("No Selector group to switch")?;
+
+    let group = proxy_groups
+        .get(&name)
+        .with_context(|| format!("Group {} not found", name))?;
+    if group.group_type != GroupType::Selector {
+        anyhow::bail!("{} is a {} group; only Selector groups can be switched", name, group.group_type.as_str());
+    }
+
+    Ok((name, group.clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::test_groups;
+
+    fn step(proxy_groups: &IndexMap<String, ProxyGroup>, step: isize) -> String {
+        step_node(&Config::default(), proxy_groups, None, step).unwrap().1
+    }
+
+    #[test]
+    fn step_node_wraps_around_both_ways() {
+        let first = test_groups(vec![ProxyGroup::test("PROXY", GroupType::Selector, "a", &["a", "b", "c"])]);
+        let last = test_groups(vec![ProxyGroup::test("PROXY", GroupType::Selector, "c", &["a", "b", "c"])]);
+
+        assert_eq!(step(&first, 1), "b");
+        assert_eq!(step(&first, -1), "c");
+        assert_eq!(step(&last, 1), "a");
+        assert_eq!(step(&last, -1), "b");
+        assert_eq!(step(&first, 4), "b");
+    }
+
+    #[test]
+    fn step_node_starts_from_the_first_member_when_the_current_one_is_unknown() {
+        let groups = test_groups(vec![ProxyGroup::test("PROXY", GroupType::Selector, "gone", &["a", "b"])]);
+        assert_eq!(step(&groups, 1), "b");
+    }
+
+    #[test]
+    fn step_node_rejects_empty_and_automatic_groups() {
+        let empty = test_groups(vec![ProxyGroup::test("PROXY", GroupType::Selector, "", &[])]);
+        assert!(step_node(&Config::default(), &empty, None, 1).is_err());
+
+        let auto = test_groups(vec![ProxyGroup::test("Auto", GroupType::URLTest, "a", &["a", "b"])]);
+        let name = "Auto".to_string();
+        assert!(step_node(&Config::default(), &auto, Some(&name), 1).is_err());
+    }
+
+    #[test]
+    fn the_default_group_is_the_first_shown_selector() {
+        let mut hidden = ProxyGroup::test("Hidden", GroupType::Selector, "a", &["a"]);
+        hidden.hidden = true;
+        let groups = test_groups(vec![
+            ProxyGroup::test("Auto", GroupType::URLTest, "a", &["a"]),
+            hidden,
+            ProxyGroup::test("PROXY", GroupType::Selector, "a", &["a"]),
+            ProxyGroup::test("Streaming", GroupType::Selector, "a", &["a"]),
+        ]);
+
+        let mut config = Config::default();
+        assert_eq!(target_group(&config, &groups, None).unwrap().0, "PROXY");
+
+        config.proxy_groups = vec!["Streaming".to_string()];
+        assert_eq!(target_group(&config, &groups, None).unwrap().0, "Streaming");
+
+        config.hotkeys.group = "PROXY".to_string();
+        assert_eq!(target_group(&config, &groups, None).unwrap().0, "PROXY");
+    }
+
+    #[test]
+    fn bind_command_maps_actions_to_arguments() {
+        assert_eq!(bind_command("next").as_deref(), Some("next"));
+        assert_eq!(bind_command("pick").as_deref(), Some("pick"));
+        assert_eq!(bind_command("toggle-mode").as_deref(), Some("mode toggle"));
+        assert_eq!(bind_command("mode:rule").as_deref(), Some("mode rule"));
+        assert_eq!(bind_command("preset:work").as_deref(), Some("preset work"));
+        assert_eq!(bind_command("refresh"), None);
+    }
+
+    #[test]
+    fn bind_command_quotes_names_for_the_shell() {
+        assert_eq!(bind_command("preset:Work mode").as_deref(), Some("preset 'Work mode'"));
+        assert_eq!(bind_command("preset:it's").as_deref(), Some(r"preset 'it'\''s'"));
+        assert_eq!(bind_command("preset:$(reboot)").as_deref(), Some("preset '$(reboot)'"));
+        assert_eq!(shell_quote(""), "''");
+        assert_eq!(shell_quote("home-1.lan:9090"), "home-1.lan:9090");
+    }
+}
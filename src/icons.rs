@@ -0,0 +1,145 @@
+use anyhow::{Context, Result};
+use indexmap::IndexMap;
+use reqwest::Client;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::future::Future;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use tracing::warn;
+#[cfg(not(feature = "sni"))]
+use tray_icon::menu::Icon;
+#[cfg(feature = "sni")]
+use crate::sni::Icon;
+
+use crate::models::ProxyGroup;
+
+const ICON_SIZE: u32 = 16;
+
+/// Group icons (mihomo's `icon` field), downloaded once into the cache directory.
+pub struct IconCache {
+    client: Client,
+    dir: PathBuf,
+    // `None` records a failed fetch so it is not retried on every refresh
+    icons: HashMap<String, Option<Icon>>,
+    /// URLs being downloaded in the background
+    pending: HashSet<String>,
+}
+
+impl IconCache {
+    pub fn new() -> Result<Self> {
+        let client = Client::builder()
+            .timeout(std::time::Duration::from_secs(5))
+            .build()
+            .context("Failed to create HTTP client")?;
+
+        let dir = dirs::cache_dir()
+            .context("Failed to get cache directory")?
+            .join("clbar")
+            .join("icons");
+
+        Ok(IconCache {
+            client,
+            dir,
+            icons: HashMap::new(),
+            pending: HashSet::new(),
+        })
+    }
+
+    pub fn get(&self, url: &str) -> Option<Icon> {
+        self.icons.get(url).cloned().flatten()
+    }
+
+    /// Returns a download of the icons of all groups not seen yet, meant for
+    /// a spawned task; hand its result to `insert`. None if nothing is missing.
+    pub fn fetch_missing(
+        &mut self,
+        proxy_groups: &IndexMap<String, ProxyGroup>,
+    ) -> Option<impl Future<Output = Vec<FetchedIcon>> + Send + 'static> {
+        let mut missing = Vec::new();
+        for group in proxy_groups.values() {
+            let Some(ref url) = group.icon else {
+                continue;
+            };
+            if url.is_empty() || self.icons.contains_key(url) || self.pending.contains(url) {
+                continue;
+            }
+            self.pending.insert(url.clone());
+            missing.push((group.name.clone(), url.clone(), self.cache_path(url)));
+        }
+        if missing.is_empty() {
+            return None;
+        }
+
+        let client = self.client.clone();
+        let dir = self.dir.clone();
+        Some(async move {
+            let mut fetched = Vec::new();
+            for (group, url, path) in missing {
+                let bytes = read_or_download(&client, &dir, &url, &path).await;
+                fetched.push(FetchedIcon { group, url, bytes });
+            }
+            fetched
+        })
+    }
+
+    /// Decodes downloaded icons into the cache.
+    pub fn insert(&mut self, fetched: Vec<FetchedIcon>) {
+        for FetchedIcon { group, url, bytes } in fetched {
+            self.pending.remove(&url);
+            let icon = match bytes.and_then(|bytes| decode(&bytes)) {
+                Ok(icon) => Some(icon),
+                Err(e) => {
+                    warn!(%group, "Failed to load icon: {}", e);
+                    None
+                }
+            };
+            self.icons.insert(url, icon);
+        }
+    }
+
+    fn cache_path(&self, url: &str) -> PathBuf {
+        let mut hasher = DefaultHasher::new();
+        url.hash(&mut hasher);
+        self.dir.join(format!("{:016x}", hasher.finish()))
+    }
+}
+
+/// Raw icon data from the disk cache or a download, not decoded yet.
+pub struct FetchedIcon {
+    group: String,
+    url: String,
+    bytes: Result<Vec<u8>>,
+}
+
+async fn read_or_download(client: &Client, dir: &Path, url: &str, path: &Path) -> Result<Vec<u8>> {
+    if path.exists() {
+        return fs::read(path).context("Failed to read cached icon");
+    }
+
+    let response = client
+        .get(url)
+        .send()
+        .await
+        .context("Failed to download icon")?;
+
+    if !response.status().is_success() {
+        anyhow::bail!("Icon download returned error: {}", response.status());
+    }
+
+    let bytes = response.bytes().await.context("Failed to read icon body")?.to_vec();
+
+    fs::create_dir_all(dir).context("Failed to create icon cache directory")?;
+    fs::write(path, &bytes).context("Failed to write cached icon")?;
+    Ok(bytes)
+}
+
+fn decode(bytes: &[u8]) -> Result<Icon> {
+    let img = image::load_from_memory(bytes)
+        .context("Failed to decode icon")?
+        .thumbnail(ICON_SIZE, ICON_SIZE)
+        .to_rgba8();
+    let (width, height) = img.dimensions();
+    Icon::from_rgba(img.into_raw(), width, height).context("Failed to create menu icon")
+}
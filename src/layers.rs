@@ -0,0 +1,282 @@
+use anyhow::{Context, Result};
+use indexmap::IndexMap;
+use std::fs;
+use std::path::Path;
+use toml::{Table, Value};
+
+use crate::config::Config;
+
+/// Read before the user's config, so distributions can ship defaults.
+pub const SYSTEM_CONFIG: &str = "/etc/clbar/config.toml";
+
+/// Environment variables starting with this prefix override single keys,
+/// e.g. `CLBAR_REFRESH_INTERVAL_SECS=10` or `CLBAR_WATCHDOG__ENABLED=true`.
+const ENV_PREFIX: &str = "CLBAR_";
+
+/// Variables with the prefix that are not config keys.
+const ENV_RESERVED: [&str; 1] = ["CLBAR_CONFIG"];
+
+/// The effective config and where each of its values came from.
+pub struct Layered {
+    pub config: Config,
+    /// Dotted key to source; keys missing here are defaults
+    pub sources: IndexMap<String, String>,
+    /// The config files read, in order, as (source, content)
+    pub files: Vec<(String, String)>,
+}
+
+/// Merges the system config, the user config (`user_content` when given,
+/// otherwise the file at `user_path`), `CLBAR_*` variables and `--set` overrides.
+pub fn load(user_path: &Path, user_content: Option<&str>, overrides: &[String]) -> Result<Layered> {
+    let defaults = Table::try_from(Config::default()).context("Failed to serialize default config")?;
+    let mut merged = Table::new();
+    let mut sources = IndexMap::new();
+    let mut files = Vec::new();
+
+    let system_path = Path::new(SYSTEM_CONFIG);
+    if system_path.exists() {
+        let content = fs::read_to_string(system_path)
+            .with_context(|| format!("Failed to read {}", SYSTEM_CONFIG))?;
+        let layer = parse_file(system_path, &content)?;
+        merge(&mut merged, layer, "", SYSTEM_CONFIG, &mut sources);
+        files.push((SYSTEM_CONFIG.to_string(), content));
+    }
+
+    let user_content = match user_content {
+        Some(content) => Some(content.to_string()),
+        None if user_path.exists() => Some(
+            fs::read_to_string(user_path).context("Failed to read config file")?,
+        ),
+        None => None,
+    };
+    if let Some(content) = user_content {
+        let layer = parse_file(user_path, &content)?;
+        let source = user_path.display().to_string();
+        merge(&mut merged, layer, "", &source, &mut sources);
+        files.push((source, content));
+    }
+
+    let mut variables: Vec<(String, String)> = std::env::vars()
+        .filter(|(name, _)| env_key(name).is_some())
+        .collect();
+    variables.sort();
+    for (name, raw) in variables {
+        let key = env_key(&name).expect("filtered above");
+        // Other CLBAR_ variables, such as the default secret, are not config keys
+        let Some(default) = lookup(&defaults, &key) else {
+            continue;
+        };
+        let value = parse_value(&raw, default);
+        set(&mut merged, &key, value, &format!("${}", name), &mut sources)?;
+    }
+
+    for spec in overrides {
+        let (key, raw) = spec
+            .split_once('=')
+            .with_context(|| format!("--set expects key=value, got {}", spec))?;
+        let key = key.trim();
+        let default = lookup(&defaults, key).with_context(|| format!("Unknown config key {}", key))?;
+        let value = parse_value(raw.trim(), default);
+        set(&mut merged, key, value, &format!("--set {}", key), &mut sources)?;
+    }
+
+    let config = Value::Table(merged)
+        .try_into()
+        .context("Invalid value in config override")?;
+
+    Ok(Layered { config, sources, files })
+}
+
+/// Parses one config file, checking it against `Config` first so type errors
+/// point at the line and column in that file.
+fn parse_file(path: &Path, content: &str) -> Result<Table> {
+    toml::from_str::<Config>(content)
+        .map_err(|e| anyhow::anyhow!("Failed to parse {}: {}", path.display(), e))?;
+
+    toml::from_str(content).map_err(|e| anyhow::anyhow!("Failed to parse {}: {}", path.display(), e))
+}
+
+/// Deep-merges `layer` into `target`; arrays and scalars replace earlier values.
+fn merge(
+    target: &mut Table,
+    layer: Table,
+    prefix: &str,
+    source: &str,
+    sources: &mut IndexMap<String, String>,
+) {
+    for (key, value) in layer {
+        let path = join(prefix, &key);
+        match (target.get_mut(&key), value) {
+            (Some(Value::Table(existing)), Value::Table(table)) => {
+                merge(existing, table, &path, source, sources);
+            }
+            (_, value) => {
+                record(&path, &value, source, sources);
+                target.insert(key, value);
+            }
+        }
+    }
+}
+
+fn record(path: &str, value: &Value, source: &str, sources: &mut IndexMap<String, String>) {
+    match value {
+        Value::Table(table) => {
+            for (key, value) in table {
+                record(&join(path, key), value, source, sources);
+            }
+        }
+        _ => {
+            sources.insert(path.to_string(), source.to_string());
+        }
+    }
+}
+
+fn set(
+    target: &mut Table,
+    key: &str,
+    value: Value,
+    source: &str,
+    sources: &mut IndexMap<String, String>,
+) -> Result<()> {
+    let mut table = target;
+    let mut segments = key.split('.').peekable();
+
+    while let Some(segment) = segments.next() {
+        if segments.peek().is_none() {
+            record(key, &value, source, sources);
+            table.insert(segment.to_string(), value);
+            return Ok(());
+        }
+
+        table = table
+            .entry(segment.to_string())
+            .or_insert_with(|| Value::Table(Table::new()))
+            .as_table_mut()
+            .with_context(|| format!("{} is not a table", segment))?;
+    }
+
+    Ok(())
+}
+
+/// The dotted config key for a `CLBAR_*` variable: `CLBAR_WATCHDOG__ENABLED`
+/// is `watchdog.enabled`. None for other and reserved variables.
+fn env_key(name: &str) -> Option<String> {
+    if ENV_RESERVED.contains(&name) {
+        return None;
+    }
+    let key = name.strip_prefix(ENV_PREFIX)?;
+    Some(key.to_lowercase().replace("__", "."))
+}
+
+fn lookup<'a>(table: &'a Table, key: &str) -> Option<&'a Value> {
+    let mut segments = key.split('.');
+    let mut value = table.get(segments.next()?)?;
+    for segment in segments {
+        value = value.as_table()?.get(segment)?;
+    }
+    Some(value)
+}
+
+/// Strings are taken verbatim; other keys accept any TOML value, so lists
+/// can be written as `["PROXY", "Streaming"]`.
+fn parse_value(raw: &str, default: &Value) -> Value {
+    if default.is_str() {
+        return Value::String(raw.to_string());
+    }
+
+    toml::from_str::<Table>(&format!("value = {}", raw))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| Value::String(raw.to_string()))
+}
+
+fn join(prefix: &str, key: &str) -> String {
+    if prefix.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", prefix, key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(content: &str) -> Table {
+        toml::from_str(content).expect("valid TOML")
+    }
+
+    #[test]
+    fn env_key_maps_double_underscores_to_dots() {
+        assert_eq!(env_key("CLBAR_REFRESH_INTERVAL_SECS").as_deref(), Some("refresh_interval_secs"));
+        assert_eq!(env_key("CLBAR_WATCHDOG__ENABLED").as_deref(), Some("watchdog.enabled"));
+        assert_eq!(env_key("CLBAR_LOGS__NOTIFY_PATTERN").as_deref(), Some("logs.notify_pattern"));
+        assert_eq!(env_key("CLBAR_CONFIG"), None);
+        assert_eq!(env_key("HOME"), None);
+    }
+
+    #[test]
+    fn parse_value_follows_the_default_type() {
+        assert_eq!(parse_value("10", &Value::Integer(5)), Value::Integer(10));
+        assert_eq!(parse_value("true", &Value::Boolean(false)), Value::Boolean(true));
+        assert_eq!(
+            parse_value(r#"["PROXY", "Streaming"]"#, &Value::Array(Vec::new())),
+            Value::Array(vec![Value::String("PROXY".into()), Value::String("Streaming".into())])
+        );
+        // Strings are verbatim, even when they look like another type
+        assert_eq!(parse_value("42", &Value::String(String::new())), Value::String("42".into()));
+        assert_eq!(parse_value("\"quoted\"", &Value::String(String::new())), Value::String("\"quoted\"".into()));
+        // Unparsable input is kept as a string and left for deserialization to reject
+        assert_eq!(parse_value("ten", &Value::Integer(5)), Value::String("ten".into()));
+    }
+
+    #[test]
+    fn merge_replaces_scalars_and_arrays_and_records_sources() {
+        let mut merged = Table::new();
+        let mut sources = IndexMap::new();
+        merge(
+            &mut merged,
+            table("proxy_groups = [\"A\", \"B\"]\n[watchdog]\nenabled = true\ninterval_secs = 30\n"),
+            "",
+            "system",
+            &mut sources,
+        );
+        merge(
+            &mut merged,
+            table("proxy_groups = [\"C\"]\n[watchdog]\ninterval_secs = 10\n"),
+            "",
+            "user",
+            &mut sources,
+        );
+
+        assert_eq!(merged, table("proxy_groups = [\"C\"]\n[watchdog]\nenabled = true\ninterval_secs = 10\n"));
+        assert_eq!(sources.get("proxy_groups").map(String::as_str), Some("user"));
+        assert_eq!(sources.get("watchdog.enabled").map(String::as_str), Some("system"));
+        assert_eq!(sources.get("watchdog.interval_secs").map(String::as_str), Some("user"));
+        assert_eq!(sources.get("watchdog"), None);
+    }
+
+    #[test]
+    fn set_creates_tables_and_records_the_key() {
+        let mut merged = table("[logs]\nlevel = \"info\"\n");
+        let mut sources = IndexMap::new();
+        set(&mut merged, "logs.level", Value::String("error".into()), "--set logs.level", &mut sources).unwrap();
+        set(&mut merged, "watchdog.enabled", Value::Boolean(true), "$CLBAR_WATCHDOG__ENABLED", &mut sources).unwrap();
+
+        assert_eq!(merged, table("[logs]\nlevel = \"error\"\n[watchdog]\nenabled = true\n"));
+        assert_eq!(sources.get("logs.level").map(String::as_str), Some("--set logs.level"));
+        assert_eq!(sources.get("watchdog.enabled").map(String::as_str), Some("$CLBAR_WATCHDOG__ENABLED"));
+
+        let mut scalar = table("refresh_interval_secs = 5\n");
+        assert!(set(&mut scalar, "refresh_interval_secs.x", Value::Integer(1), "test", &mut sources).is_err());
+    }
+
+    #[test]
+    fn lookup_finds_nested_defaults() {
+        let defaults = Table::try_from(Config::default()).unwrap();
+        assert!(lookup(&defaults, "watchdog.enabled").is_some_and(Value::is_bool));
+        assert!(lookup(&defaults, "refresh_interval_secs").is_some_and(Value::is_integer));
+        assert!(lookup(&defaults, "watchdog.missing").is_none());
+        assert!(lookup(&defaults, "refresh_interval_secs.nested").is_none());
+    }
+}
@@ -0,0 +1,95 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::io::IsTerminal;
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{Builder, Rotation};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, EnvFilter};
+
+use crate::config::LoggingConfig;
+
+pub const LEVELS: [&str; 5] = ["error", "warn", "info", "debug", "trace"];
+
+/// Sets up clbar's log: `RUST_LOG` or `level` filters it, each `--verbose`
+/// raises clbar's own messages a level, and output goes to the journal under
+/// systemd and to stderr otherwise, optionally mirrored to a rotated file.
+///
+/// The returned guard flushes the file on drop, so keep it until exit.
+pub fn init(config: &LoggingConfig, verbose: u8) -> Result<Option<WorkerGuard>> {
+    let mut filter = match std::env::var("RUST_LOG") {
+        Ok(directives) if !directives.is_empty() => {
+            EnvFilter::try_new(&directives).with_context(|| format!("Invalid RUST_LOG: {}", directives))?
+        }
+        // Dependencies only speak up about problems unless asked to
+        _ => EnvFilter::try_new(format!("warn,clbar={}", config.level))
+            .with_context(|| format!("Invalid logging level: {}", config.level))?,
+    };
+    match verbose {
+        0 => {}
+        1 => filter = filter.add_directive("clbar=debug".parse()?),
+        _ => filter = filter.add_directive("clbar=trace".parse()?),
+    }
+
+    let journald = if under_journald() {
+        tracing_journald::layer().ok()
+    } else {
+        None
+    };
+    let stderr = journald
+        .is_none()
+        .then(|| {
+            fmt::layer()
+                .with_writer(std::io::stderr)
+                .with_ansi(std::io::stderr().is_terminal())
+                .with_target(verbose > 0)
+        });
+
+    let (file, guard) = if config.file {
+        let dir = log_dir()?;
+        fs::create_dir_all(&dir).context("Failed to create log directory")?;
+        let appender = Builder::new()
+            .rotation(Rotation::DAILY)
+            .filename_prefix("clbar")
+            .filename_suffix("log")
+            .max_log_files(config.max_files.max(1))
+            .build(&dir)
+            .with_context(|| format!("Failed to open a log file in {}", dir.display()))?;
+        let (writer, guard) = tracing_appender::non_blocking(appender);
+        (Some(fmt::layer().with_writer(writer).with_ansi(false)), Some(guard))
+    } else {
+        (None, None)
+    };
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(journald)
+        .with(stderr)
+        .with(file)
+        .try_init()
+        .context("Failed to set up logging")?;
+
+    Ok(guard)
+}
+
+/// `$XDG_STATE_HOME/clbar/logs`, next to the remembered selections.
+pub fn log_dir() -> Result<PathBuf> {
+    let state_dir = dirs::state_dir()
+        .or_else(dirs::data_local_dir)
+        .context("Failed to get state directory")?;
+    Ok(state_dir.join("clbar").join("logs"))
+}
+
+/// systemd sets `JOURNAL_STREAM` to the device and inode of the stream it
+/// connected to stderr; checking them avoids trusting a leaked variable.
+fn under_journald() -> bool {
+    let Ok(stream) = std::env::var("JOURNAL_STREAM") else {
+        return false;
+    };
+    let Ok(stderr) = fs::metadata("/proc/self/fd/2") else {
+        return false;
+    };
+    stream == format!("{}:{}", stderr.dev(), stderr.ino())
+}
@@ -0,0 +1,91 @@
+use anyhow::{Context, Result};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::cli;
+use crate::models::LogEntry;
+
+pub const LOG_LEVELS: [&str; 5] = ["debug", "info", "warning", "error", "silent"];
+
+/// Bounded buffer of the most recent core log lines.
+pub struct LogBuffer {
+    entries: VecDeque<LogEntry>,
+    capacity: usize,
+}
+
+impl LogBuffer {
+    pub fn new(capacity: usize) -> Self {
+        LogBuffer {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, entry: LogEntry) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    /// The last `count` warning and error lines, oldest first.
+    pub fn recent_problems(&self, count: usize) -> Vec<LogEntry> {
+        let mut problems: Vec<LogEntry> = self
+            .entries
+            .iter()
+            .rev()
+            .filter(|entry| is_problem(entry))
+            .take(count)
+            .cloned()
+            .collect();
+        problems.reverse();
+        problems
+    }
+}
+
+pub fn is_problem(entry: &LogEntry) -> bool {
+    entry.level == "warning" || entry.level == "error"
+}
+
+/// `clbar logs [--level <level>] [--follow]`
+///
+/// The core only streams new lines, so without `--follow` this prints what
+/// arrives within a short window and exits.
+pub async fn run_cli(args: &[String], controller: Option<&str>) -> Result<()> {
+    let mut level = "info".to_string();
+    let mut follow = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--level" | "-l" => {
+                level = args.next().context("--level requires a value")?.clone();
+            }
+            "--follow" | "-f" => follow = true,
+            other => anyhow::bail!("Unknown argument for logs: {}", other),
+        }
+    }
+
+    if !LOG_LEVELS.contains(&level.as_str()) {
+        anyhow::bail!("Invalid log level {} (expected one of: {})", level, LOG_LEVELS.join(", "));
+    }
+
+    let (_, clash_api, _) = cli::connect(controller).await?;
+
+    let mut receiver = clash_api.stream_logs(&level);
+    let deadline = tokio::time::sleep(Duration::from_secs(2));
+    tokio::pin!(deadline);
+
+    loop {
+        tokio::select! {
+            entry = receiver.recv() => match entry {
+                Some(entry) => println!("[{}] {}", entry.level, entry.payload),
+                None => return Ok(()),
+            },
+            _ = &mut deadline, if !follow => return Ok(()),
+        }
+    }
+}
@@ -1,128 +1,658 @@
 mod clash_api;
+mod cli;
 mod config;
+mod controller;
+mod hotkeys;
+mod icons;
+mod layers;
+mod logging;
+mod logs;
+mod state;
 mod models;
+mod notifications;
+mod presets;
+mod secret;
+#[cfg(feature = "gtk-tray")]
+mod settings;
+#[cfg(feature = "sni")]
+mod sni;
+mod tls;
+mod transport;
 mod tray;
+mod validate;
+mod watchdog;
+
+#[cfg(not(any(feature = "gtk-tray", feature = "sni")))]
+compile_error!("enable the gtk-tray or sni feature to get a tray backend");
 
 use anyhow::{Context, Result};
-use clash_api::ClashApi;
-use config::Config;
-use models::TrayEvent;
-use notify_rust::Notification;
+use config::{Config, LoggingConfig};
+use controller::{Controller, Poll, Refreshed};
+use icons::{FetchedIcon, IconCache};
+use logs::LogBuffer;
 use indexmap::IndexMap;
-use std::time::Duration;
+use models::{CoreConfig, LogEntry, MemoryStats, ProxyGroup, SwitchReport, TrayEvent};
+use notifications::{NotificationKind, Notifier};
+use regex::Regex;
+#[cfg(feature = "gtk-tray")]
+use settings::{SettingsEvent, SettingsValues, SettingsWindow};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, watch};
+use tracing::{debug, error, info, warn};
 use tray::TrayManager;
+use watchdog::{Failover, Watchdog};
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Initialize GTK (required for tray-icon)
+    // Command-line subcommands run without the tray
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (controller, config_options, verbose, args) = cli::take_global_options(&args)?;
+    Config::set_options(config_options);
+    if !args.is_empty() {
+        // Commands print their results; the log only carries warnings unless -v
+        let cli_logging = LoggingConfig {
+            level: "warn".to_string(),
+            ..LoggingConfig::default()
+        };
+        logging::init(&cli_logging, verbose)?;
+    }
+    let controller = controller.as_deref();
+    match args.first().map(String::as_str) {
+        Some("logs") => return logs::run_cli(&args[1..], controller).await,
+        Some("reload") => return cli::reload(&args[1..], controller).await,
+        Some("restart") => return cli::restart(&args[1..], controller).await,
+        Some("update-geo") => return cli::update_geo(&args[1..], controller).await,
+        Some("preset") => return cli::preset(&args[1..], controller).await,
+        Some(action @ ("next" | "prev" | "fastest" | "pick" | "mode")) => {
+            return hotkeys::run(action, &args[1..], controller).await
+        }
+        Some("hyprland-binds") => return hotkeys::hyprland_binds(&args[1..], controller),
+        Some("config") => return cli::config(&args[1..]),
+        Some("check-config") => return cli::check_config(&args[1..], controller).await,
+        Some(other) => anyhow::bail!("Unknown command: {}", other),
+        None => {}
+    }
+
+    // Initialize GTK (required for tray-icon and the settings window)
+    #[cfg(feature = "gtk-tray")]
     gtk::init().context("Failed to initialize GTK")?;
 
     // Load configuration
-    let config = Config::load().context("Failed to load configuration")?;
+    let (mut config, config_warnings) = Config::load_checked().context("Failed to load configuration")?;
+    let _log_guard = logging::init(&config.logging, verbose)?;
 
-    println!("Clash Node Switcher (clbar) starting...");
-    println!("Config loaded from: ~/.config/clbar/config.toml");
-    println!("Clash API URL: {}", config.clash_api_url);
+    let mut notifier = Notifier::new(&config.notifications);
 
-    // Initialize Clash API client
-    let clash_api = ClashApi::new(&config).context("Failed to create Clash API client")?;
+    info!("Clash Node Switcher (clbar) starting...");
+    info!(path = %Config::config_path()?.display(), "Config loaded");
 
-    // Test connection
-    if let Err(e) = clash_api.test_connection().await {
-        eprintln!("Failed to connect to Clash API: {}", e);
-        notify_error("Connection Failed", &format!("Cannot connect to Clash API: {}", e));
+    // Questionable values are reported but do not stop the tray from starting
+    if !config_warnings.is_empty() {
+        let lines: Vec<String> = config_warnings.iter().map(ToString::to_string).collect();
+        for line in &lines {
+            warn!("Config {}", line);
+        }
+        notifier.show(NotificationKind::Error, "Config Warnings", &lines.join("\n"));
+    }
+
+    // Connect to every configured controller; unreachable ones are retried on refresh
+    let mut controllers = Vec::new();
+    for controller_config in config.controllers() {
+        info!(controller = %controller_config.name, url = %controller_config.url, "Clash API URL");
+        let mut controller = Controller::new(&controller_config, &config)?;
+        if controller_config.tls.insecure && controller_config.tls.fingerprint.is_empty() {
+            notifier.show(
+                NotificationKind::Error,
+                "Insecure Connection",
+                &format!("TLS certificate verification is disabled for {}", controller_config.name),
+            );
+        }
+        match controller.connect(&config).await {
+            Ok(_) => info!(controller = %controller.name, "Connected successfully"),
+            Err(e) => error!(controller = %controller.name, "Failed to connect: {}", e),
+        }
+        controllers.push(controller);
+    }
+
+    if !controllers.iter().any(Controller::is_connected) {
+        error!("Failed to connect to any Clash API");
+        notifier.show(NotificationKind::Error, "Connection Failed", "Cannot connect to any Clash API");
         std::process::exit(1);
     }
 
-    println!("Connected to Clash API successfully");
+    // The menu shows one controller at a time, starting with the first reachable one
+    let mut active = controllers.iter().position(Controller::is_connected).unwrap_or(0);
+
+    // Live memory and log streams follow the active controller
+    let (mut memory_stats, mut log_receiver) = start_streams(&controllers[active], &config);
+    let mut log_buffer = LogBuffer::new(config.logs.buffer_size);
+    let log_notify_pattern = if config.logs.notify_pattern.is_empty() {
+        None
+    } else {
+        Some(Regex::new(&config.logs.notify_pattern).context("Invalid logs notify_pattern")?)
+    };
 
     // Initialize tray icon
     let mut tray_manager = TrayManager::new().context("Failed to create tray manager")?;
 
-    // Initial proxy groups fetch
-    let (mut proxy_groups, mut node_delays) = fetch_proxy_groups(&clash_api, &config).await?;
-    tray_manager.update_menu(&proxy_groups, &node_delays, config.delay_green_threshold, config.delay_yellow_threshold)?;
-    tray_manager.set_icon_connected()?;
+    // Group icons are downloaded once and cached on disk
+    let mut icon_cache = IconCache::new().context("Failed to create icon cache")?;
+    // Slow work runs in spawned tasks that report back here
+    let (background, mut background_results) = mpsc::unbounded_channel();
+    fetch_icons(&mut icon_cache, &controllers[active].proxy_groups, &background);
+    update_tray(&mut tray_manager, &controllers, active, &config, &icon_cache)?;
+    refresh_core_config(&controllers[active], &mut tray_manager).await;
+
+    info!("Tray icon initialized with {} proxy groups", controllers[active].proxy_groups.len());
 
-    println!("Tray icon initialized with {} proxy groups", proxy_groups.len());
+    // Events from finished background work, for the controller they were started on
+    let mut queued: VecDeque<(String, TrayEvent)> = VecDeque::new();
 
     // Event loop
-    let mut last_refresh = std::time::Instant::now();
-    let refresh_interval = Duration::from_secs(config.refresh_interval_secs);
+    let mut last_refresh = Instant::now();
+    #[cfg_attr(not(feature = "gtk-tray"), allow(unused_mut))]
+    let mut refresh_interval = Duration::from_secs(config.refresh_interval_secs);
+    #[cfg(feature = "gtk-tray")]
+    let mut settings_window: Option<SettingsWindow> = None;
 
     loop {
         // Poll tray events
-        while let Some(event) = tray_manager.poll_events() {
+        // Menu items, icon clicks and notification buttons share the handlers
+        while let Some((target, event)) = next_event(&mut tray_manager, &notifier, &mut queued, &controllers, active) {
+            let controller = &mut controllers[target];
             match event {
                 TrayEvent::SwitchNode { group, node } => {
-                    println!("Switching {} to {}", group, node);
-                    handle_switch_node(&clash_api, &mut tray_manager, &group, &node).await;
+                    info!(%group, %node, "Switching node");
+                    handle_switch_node(controller, &mut notifier, &group, &node).await;
                     // Refresh proxy groups after switch
-                    if let Ok((groups, delays)) = fetch_proxy_groups(&clash_api, &config).await {
-                        proxy_groups = groups;
-                        node_delays = delays;
-                        tray_manager.update_menu(&proxy_groups, &node_delays, config.delay_green_threshold, config.delay_yellow_threshold)?;
+                    controller.fetch_groups(&config).await.ok();
+                    update_tray(&mut tray_manager, &controllers, active, &config, &icon_cache)?;
+                }
+                TrayEvent::UnpinGroup { group } => {
+                    info!(%group, "Unpinning group");
+                    match controller.clash_api.unpin_group(&group).await {
+                        Ok(_) => {
+                            // An unpinned group should not be re-pinned on restore
+                            if let Err(e) = controller.selections.forget(&group) {
+                                warn!("Failed to forget selection: {}", e);
+                            }
+                            notifier.show(NotificationKind::Switch, "Group Unpinned", &format!("{} is automatic again", group));
+                        }
+                        Err(e) => {
+                            error!(%group, "Failed to unpin group: {}", e);
+                            notifier.show_with_action(
+                                NotificationKind::Error,
+                                "Unpin Failed",
+                                &format!("Failed to unpin {}: {}", group, e),
+                                "Retry",
+                                &controller.name,
+                                TrayEvent::UnpinGroup { group: group.clone() },
+                            );
+                        }
+                    }
+                    controller.fetch_groups(&config).await.ok();
+                    update_tray(&mut tray_manager, &controllers, active, &config, &icon_cache)?;
+                }
+                TrayEvent::PatchCoreConfig(patch) => {
+                    let description = patch.describe();
+                    info!("Updating core config: {}", description);
+                    match controller.clash_api.patch_configs(&patch).await {
+                        Ok(_) => notifier.show(NotificationKind::Action, "Core Settings", &format!("Set {}", description)),
+                        Err(e) => {
+                            error!("Failed to update core config: {}", e);
+                            notifier.show_with_action(
+                                NotificationKind::Error,
+                                "Core Settings Failed",
+                                &format!("Failed to set {}: {}", description, e),
+                                "Retry",
+                                &controller.name,
+                                TrayEvent::PatchCoreConfig(patch.clone()),
+                            );
+                        }
+                    }
+                    if target == active {
+                        refresh_core_config(controller, &mut tray_manager).await;
+                    }
+                }
+                TrayEvent::ReloadConfig | TrayEvent::RestartCore | TrayEvent::UpdateGeo => {
+                    let clash_api = &controller.clash_api;
+                    let (action, done, result) = match event {
+                        TrayEvent::ReloadConfig => ("Reload", "Core config reloaded", clash_api.reload_config("").await),
+                        TrayEvent::RestartCore => ("Restart", "Core restarted", clash_api.restart().await),
+                        _ => ("Geo Update", "GeoIP/GeoSite databases updated", clash_api.update_geo().await),
+                    };
+                    match result {
+                        Ok(_) => {
+                            info!("{}", done);
+                            notifier.show(NotificationKind::Action, &format!("{} Succeeded", action), done);
+                        }
+                        Err(e) => {
+                            error!("{} failed: {}", action, e);
+                            notifier.show_with_action(
+                                NotificationKind::Error,
+                                &format!("{} Failed", action),
+                                &e.to_string(),
+                                "Retry",
+                                &controller.name,
+                                event.clone(),
+                            );
+                            continue;
+                        }
+                    }
+
+                    // The core drops its groups while reloading; wait for it in the background
+                    let name = controller.name.clone();
+                    let clash_api = controller.clash_api.clone();
+                    let group_types = config.group_types.clone();
+                    let background = background.clone();
+                    tokio::spawn(async move {
+                        let result = controller::wait_for_core(clash_api, group_types).await;
+                        background.send(Background::CoreBack { controller: name, action, result }).ok();
+                    });
+                }
+                TrayEvent::ApplyPreset { name } => {
+                    let Some(preset) = config.presets.iter().find(|preset| preset.name == name) else {
+                        continue;
+                    };
+                    info!(preset = %name, "Applying preset");
+                    let report = presets::apply(&controller.clash_api, preset, &controller.proxy_groups).await;
+                    if let Err(e) = controller.selections.remember_all(&report.switched) {
+                        warn!("Failed to remember selections: {}", e);
+                    }
+                    if report.failed.is_empty() {
+                        notifier.show(NotificationKind::Switch, "Preset Applied", &format!("{}\n{}", name, report.switched_lines().join("\n")));
+                    } else {
+                        warn!(preset = %name, "Preset partially failed: {}", report.failed.join(", "));
+                        notifier.show_with_action(
+                            NotificationKind::Error,
+                            "Preset Partially Applied",
+                            &format!("{}: {} switched, {} failed\n{}", name, report.switched.len(), report.failed.len(), report.failed.join("\n")),
+                            "Retry",
+                            &controller.name,
+                            TrayEvent::ApplyPreset { name: name.clone() },
+                        );
                     }
+                    controller.fetch_groups(&config).await.ok();
+                    update_tray(&mut tray_manager, &controllers, active, &config, &icon_cache)?;
+                }
+                TrayEvent::RestoreSelections => {
+                    let report = controller.restore_selections(&config).await;
+                    report_restore(&mut notifier, &controller.name, &report);
+                    update_tray(&mut tray_manager, &controllers, active, &config, &icon_cache)?;
+                }
+                TrayEvent::SavePreset => {
+                    // Pick the first free "Preset N" name; it can be renamed in the config
+                    let name = (1..)
+                        .map(|index| format!("Preset {}", index))
+                        .find(|name| !config.presets.iter().any(|preset| preset.name == *name))
+                        .unwrap_or_default();
+                    config.presets.push(presets::from_current(&name, &controller.proxy_groups));
+                    match config.save() {
+                        Ok(_) => notifier.show(NotificationKind::Action, "Preset Saved", &format!("Saved current selections as {}", name)),
+                        Err(e) => {
+                            error!("Failed to save preset: {}", e);
+                            // The unsaved preset stays in memory and is written by the next save
+                            notifier.show(NotificationKind::Error, "Preset Not Saved", &format!("Error: {}", e));
+                        }
+                    }
+                    update_tray(&mut tray_manager, &controllers, active, &config, &icon_cache)?;
+                }
+                TrayEvent::SelectController { name } => {
+                    let Some(index) = controllers.iter().position(|controller| controller.name == name) else {
+                        continue;
+                    };
+                    info!(controller = %name, "Showing controller");
+                    active = index;
+                    (memory_stats, log_receiver) = start_streams(&controllers[active], &config);
+                    log_buffer = LogBuffer::new(config.logs.buffer_size);
+                    tray_manager.update_logs(Vec::new());
+                    fetch_icons(&mut icon_cache, &controllers[active].proxy_groups, &background);
+                    update_tray(&mut tray_manager, &controllers, active, &config, &icon_cache)?;
+                    refresh_core_config(&controllers[active], &mut tray_manager).await;
+                }
+                TrayEvent::CycleNode { step } => match hotkeys::step_node(&config, &controller.proxy_groups, None, step) {
+                    Ok((group, node)) => queued.push_back((controller.name.clone(), TrayEvent::SwitchNode { group, node })),
+                    Err(e) => notifier.show_with_action(
+                        NotificationKind::Error,
+                        "Switch Failed",
+                        &format!("{:#}", e),
+                        "Retry",
+                        &controller.name,
+                        event.clone(),
+                    ),
+                },
+                TrayEvent::FastestNode | TrayEvent::PickNode => {
+                    // The picker stays open and delay tests take a while, so
+                    // both run in the background and switch when they finish
+                    let name = controller.name.clone();
+                    let clash_api = controller.clash_api.clone();
+                    let proxy_groups = controller.proxy_groups.clone();
+                    let config = config.clone();
+                    let background = background.clone();
+                    tokio::spawn(async move {
+                        let target = match event {
+                            TrayEvent::FastestNode => hotkeys::fastest_node(&config, &clash_api, &proxy_groups, None)
+                                .await
+                                .map(|(group, node, _)| Some((group, node))),
+                            _ => hotkeys::pick_node(&config, &proxy_groups, None).await,
+                        };
+                        background.send(Background::Target { controller: name, event, target }).ok();
+                    });
+                }
+                #[cfg(feature = "gtk-tray")]
+                TrayEvent::OpenSettings => {
+                    if let Some(ref window) = settings_window {
+                        window.present();
+                        continue;
+                    }
+
+                    let controller_config = match config.controller(Some(&controller.name)) {
+                        Ok(controller_config) => controller_config,
+                        Err(e) => {
+                            notifier.show(NotificationKind::Error, "Settings Unavailable", &e.to_string());
+                            continue;
+                        }
+                    };
+                    // Offer every live group in the filter, not just the ones shown now
+                    let live_groups: Vec<String> = match controller.clash_api.get_proxies(&config.group_types).await {
+                        Ok((proxy_groups, _)) => proxy_groups.into_keys().collect(),
+                        Err(_) => controller.proxy_groups.keys().cloned().collect(),
+                    };
+                    settings_window = Some(SettingsWindow::open(&config, &controller_config, &live_groups));
                 }
                 TrayEvent::Refresh => {
-                    println!("Refreshing proxy groups...");
+                    info!("Refreshing proxy groups...");
 
                     // Trigger delay test (ignore errors)
-                    if let Err(e) = clash_api.trigger_delay_test(
+                    if let Err(e) = controller.clash_api.trigger_delay_test(
                         &config.delay_test_group,
                         &config.delay_test_url,
                         config.delay_test_timeout
                     ).await {
-                        eprintln!("Delay test failed (continuing anyway): {}", e);
+                        warn!("Delay test failed (continuing anyway): {}", e);
                     } else {
-                        println!("Delay test triggered for group: {}", config.delay_test_group);
+                        debug!(group = %config.delay_test_group, "Delay test triggered");
                     }
 
-                    match fetch_proxy_groups(&clash_api, &config).await {
-                        Ok((groups, delays)) => {
-                            proxy_groups = groups;
-                            node_delays = delays;
-                            tray_manager.update_menu(&proxy_groups, &node_delays, config.delay_green_threshold, config.delay_yellow_threshold)?;
-                            tray_manager.set_icon_connected()?;
-                            notify_success("Refreshed", "Proxy groups updated successfully");
+                    match controller.refresh(&config).await {
+                        Ok(refreshed) => {
+                            report_refreshed(&mut notifier, &controller.name, &refreshed);
+                            fetch_icons(&mut icon_cache, &controller.proxy_groups, &background);
+                            if target == active {
+                                refresh_core_config(controller, &mut tray_manager).await;
+                            }
+                            notifier.show(NotificationKind::Refresh, "Refreshed", "Proxy groups updated successfully");
                         }
                         Err(e) => {
-                            eprintln!("Failed to refresh: {}", e);
-                            notify_error("Refresh Failed", &format!("Error: {}", e));
-                            tray_manager.set_icon_disconnected()?;
+                            error!("Failed to refresh: {}", e);
+                            notifier.show_with_action(
+                                NotificationKind::Error,
+                                "Refresh Failed",
+                                &format!("Error: {}", e),
+                                "Retry",
+                                &controller.name,
+                                TrayEvent::Refresh,
+                            );
                         }
                     }
+                    update_tray(&mut tray_manager, &controllers, active, &config, &icon_cache)?;
                 }
                 TrayEvent::Quit => {
-                    println!("Quitting...");
+                    info!("Quitting...");
                     std::process::exit(0);
                 }
             }
         }
 
-        // Auto-refresh proxy groups periodically
+        // Handle buttons of the settings window
+        #[cfg(feature = "gtk-tray")]
+        let mut settings_closed = false;
+        #[cfg(feature = "gtk-tray")]
+        if let Some(ref window) = settings_window {
+            while let Some(event) = window.poll() {
+                match event {
+                    SettingsEvent::TestConnection(values) => {
+                        window.set_status("Testing connection…");
+                        match config.controller(Some(&controllers[active].name)) {
+                            Ok(controller_config) => {
+                                // The test may take the whole timeout; keep the window painting meanwhile
+                                let controller_config = values.controller_config(&controller_config);
+                                let sender = window.sender();
+                                tokio::spawn(async move {
+                                    let status = match test_settings_connection(&controller_config).await {
+                                        Ok(status) => status,
+                                        Err(e) => format!("Connection failed: {}", e),
+                                    };
+                                    sender.send(SettingsEvent::Status(status)).ok();
+                                });
+                            }
+                            Err(e) => window.set_status(&format!("Connection failed: {}", e)),
+                        }
+                    }
+                    SettingsEvent::Status(status) => window.set_status(&status),
+                    SettingsEvent::Save(values) => {
+                        match apply_settings(&values, &mut config, &mut controllers, active).await {
+                            Ok(_) => {
+                                refresh_interval = Duration::from_secs(config.refresh_interval_secs);
+                                (memory_stats, log_receiver) = start_streams(&controllers[active], &config);
+                                fetch_icons(&mut icon_cache, &controllers[active].proxy_groups, &background);
+                                update_tray(&mut tray_manager, &controllers, active, &config, &icon_cache)?;
+                                refresh_core_config(&controllers[active], &mut tray_manager).await;
+                                notifier.show(NotificationKind::Action, "Settings Saved", "Configuration updated");
+                                window.close();
+                            }
+                            Err(e) => window.set_status(&format!("Not saved: {}", e)),
+                        }
+                    }
+                    SettingsEvent::Closed => settings_closed = true,
+                }
+            }
+        }
+        #[cfg(feature = "gtk-tray")]
+        if settings_closed {
+            settings_window = None;
+        }
+
+        // Auto-refresh every controller periodically, in the background so
+        // a slow or unreachable core does not hold up the menu
         if last_refresh.elapsed() >= refresh_interval {
-            // Trigger delay test (ignore errors)
-            if let Err(e) = clash_api.trigger_delay_test(
-                &config.delay_test_group,
-                &config.delay_test_url,
-                config.delay_test_timeout
-            ).await {
-                eprintln!("Auto-refresh delay test failed (continuing anyway): {}", e);
+            for (index, controller) in controllers.iter_mut().enumerate() {
+                // The previous round for this controller is still waiting
+                if controller.polling {
+                    continue;
+                }
+                controller.polling = true;
+
+                let name = controller.name.clone();
+                let poll = controller.poll(&config);
+                // The Core submenu shows the settings of the shown controller
+                let clash_api = (index == active).then(|| controller.clash_api.clone());
+                let background = background.clone();
+                tokio::spawn(async move {
+                    let poll = poll.await;
+                    let core_config = match clash_api {
+                        Some(clash_api) if poll.is_reachable() => Some(clash_api.get_configs().await),
+                        _ => None,
+                    };
+                    background.send(Background::Polled { controller: name, poll, core_config }).ok();
+                });
+            }
+            last_refresh = Instant::now();
+        }
+
+        // Collect new core log lines
+        let mut logs_changed = false;
+        while let Ok(entry) = log_receiver.try_recv() {
+            if entry.level == "error" {
+                if let Some(ref pattern) = log_notify_pattern {
+                    if pattern.is_match(&entry.payload) {
+                        notifier.show(NotificationKind::Error, "Core Error", &entry.payload);
+                    }
+                }
+            }
+            logs_changed |= logs::is_problem(&entry);
+            log_buffer.push(entry);
+        }
+        if logs_changed {
+            tray_manager.update_logs(log_buffer.recent_problems(config.logs.menu_lines));
+        }
+
+        // Pick up the latest sample from the memory stream
+        if let Some(ref mut receiver) = memory_stats {
+            if receiver.has_changed().unwrap_or(false) {
+                controllers[active].core_status.memory = receiver.borrow_and_update().clone();
+                tray_manager.update_core_status(&controllers[active].core_status)?;
+            }
+        }
+
+        // Fail over watched Selector groups whose current node is unhealthy.
+        // Delay tests can take a while, so the checks run in the background.
+        for controller in controllers.iter_mut() {
+            if !controller.is_connected() {
+                continue;
             }
+            let Some(watchdog) = controller.watchdog.take_if(|watchdog| watchdog.is_due()) else {
+                continue;
+            };
+
+            let name = controller.name.clone();
+            let clash_api = controller.clash_api.clone();
+            let proxy_groups = controller.proxy_groups.clone();
+            let config = config.clone();
+            let background = background.clone();
+            tokio::spawn(async move {
+                let (watchdog, failovers) = watchdog.check(&clash_api, &config, &proxy_groups).await;
+                let mut switches = Vec::new();
+                for failover in failovers {
+                    let result = clash_api.switch_node(&failover.group, &failover.to).await;
+                    switches.push((failover, result));
+                }
+                background.send(Background::Watchdog { controller: name, watchdog, switches }).ok();
+            });
+        }
 
-            if let Ok((groups, delays)) = fetch_proxy_groups(&clash_api, &config).await {
-                proxy_groups = groups;
-                node_delays = delays;
-                tray_manager.update_menu(&proxy_groups, &node_delays, config.delay_green_threshold, config.delay_yellow_threshold)?;
-                tray_manager.set_icon_connected()?;
-            } else {
-                tray_manager.set_icon_disconnected()?;
+        // Pick up the results of background work
+        while let Ok(result) = background_results.try_recv() {
+            match result {
+                Background::Watchdog { controller: name, watchdog, switches } => {
+                    let Some(index) = controllers.iter().position(|controller| controller.name == name) else {
+                        continue;
+                    };
+                    let controller = &mut controllers[index];
+                    // Settings may have replaced the controller and its watchdog meanwhile
+                    controller.watchdog.get_or_insert(watchdog);
+                    if switches.is_empty() {
+                        continue;
+                    }
+
+                    for (failover, result) in &switches {
+                        info!(
+                            controller = %controller.name,
+                            group = %failover.group,
+                            from = %failover.from,
+                            to = %failover.to,
+                            "Watchdog switching node: {}",
+                            failover.reason
+                        );
+                        match result {
+                            Ok(_) => {
+                                // Restores after a core restart should keep the new node
+                                if let Err(e) = controller.selections.remember(&failover.group, &failover.to) {
+                                    warn!("Failed to remember selection: {}", e);
+                                }
+                                notifier.show(
+                                    NotificationKind::Failover,
+                                    "Failover",
+                                    &format!(
+                                        "Switched {} to {}: {}",
+                                        failover.group, failover.to, failover.reason
+                                    ),
+                                );
+                            }
+                            Err(e) => {
+                                error!(controller = %controller.name, "Watchdog failed to switch node: {}", e);
+                                // No "Retry": the watchdog tries again on its next check
+                                notifier.show(
+                                    NotificationKind::Error,
+                                    "Failover Failed",
+                                    &format!("Failed to switch {}: {}", failover.group, e),
+                                );
+                            }
+                        }
+                    }
+
+                    controller.fetch_groups(&config).await.ok();
+                    update_tray(&mut tray_manager, &controllers, active, &config, &icon_cache)?;
+                }
+                Background::CoreBack { controller: name, action, result } => {
+                    let Some(index) = controllers.iter().position(|controller| controller.name == name) else {
+                        continue;
+                    };
+                    let controller = &mut controllers[index];
+                    match result {
+                        Ok(_) => match controller.resume(&config).await {
+                            Ok(report) => {
+                                report_restore(&mut notifier, &controller.name, &report);
+                                if index == active {
+                                    refresh_core_config(controller, &mut tray_manager).await;
+                                }
+                            }
+                            Err(e) => error!(controller = %name, "Core did not come back after {}: {}", action.to_lowercase(), e),
+                        },
+                        Err(e) => {
+                            controller.core_status.connected_since = None;
+                            error!(controller = %name, "Core did not come back after {}: {}", action.to_lowercase(), e);
+                        }
+                    }
+                    update_tray(&mut tray_manager, &controllers, active, &config, &icon_cache)?;
+                }
+                Background::Polled { controller: name, poll, core_config } => {
+                    let Some(index) = controllers.iter().position(|controller| controller.name == name) else {
+                        continue;
+                    };
+                    let controller = &mut controllers[index];
+                    controller.polling = false;
+
+                    let was_connected = controller.is_connected();
+                    match controller.apply_poll(&config, poll) {
+                        Ok(refreshed) => {
+                            if !was_connected {
+                                info!(controller = %controller.name, "Reconnected");
+                                notifier.show(NotificationKind::Reconnect, "Reconnected", &format!("{} is reachable again", controller.name));
+                            }
+                            report_refreshed(&mut notifier, &controller.name, &refreshed);
+                        }
+                        Err(e) => warn!(controller = %controller.name, "Auto-refresh failed: {}", e),
+                    }
+                    match core_config {
+                        Some(Ok(core_config)) if index == active => tray_manager.update_core_config(core_config),
+                        Some(Err(e)) => warn!("Failed to fetch core config: {}", e),
+                        _ => {}
+                    }
+                    update_tray(&mut tray_manager, &controllers, active, &config, &icon_cache)?;
+                }
+                Background::Icons(fetched) => {
+                    icon_cache.insert(fetched);
+                    update_tray(&mut tray_manager, &controllers, active, &config, &icon_cache)?;
+                }
+                Background::Target { controller, event, target } => match target {
+                    Ok(Some((group, node))) => queued.push_back((controller, TrayEvent::SwitchNode { group, node })),
+                    Ok(None) => {}
+                    Err(e) => notifier.show_with_action(
+                        NotificationKind::Error,
+                        "Switch Failed",
+                        &format!("{:#}", e),
+                        "Retry",
+                        &controller,
+                        event,
+                    ),
+                },
             }
-            last_refresh = std::time::Instant::now();
         }
 
         // Process GTK events (non-blocking)
+        #[cfg(feature = "gtk-tray")]
         while gtk::events_pending() {
             gtk::main_iteration();
         }
@@ -132,66 +662,242 @@ async fn main() -> Result<()> {
     }
 }
 
-async fn fetch_proxy_groups(
-    clash_api: &ClashApi,
-    config: &Config,
-) -> Result<(IndexMap<String, models::ProxyGroup>, IndexMap<String, u32>)> {
-    let (all_groups, node_delays) = clash_api.get_proxies().await?;
+/// Results of work spawned off the event loop so the menu stays responsive.
+enum Background {
+    /// The core answers again after a reload, restart or geo update, or gave up waiting
+    CoreBack {
+        controller: String,
+        action: &'static str,
+        result: Result<()>,
+    },
+    /// The node chosen by "fastest" or the picker; `event` is what to retry
+    Target {
+        controller: String,
+        event: TrayEvent,
+        target: Result<Option<(String, String)>>,
+    },
+    /// A watchdog round finished; the watchdog goes back to its controller
+    Watchdog {
+        controller: String,
+        watchdog: Watchdog,
+        switches: Vec<(Failover, Result<()>)>,
+    },
+    /// A periodic refresh finished; `core_config` is fetched for the shown controller
+    Polled {
+        controller: String,
+        poll: Poll,
+        core_config: Option<Result<CoreConfig>>,
+    },
+    /// Group icons downloaded or read from the disk cache
+    Icons(Vec<FetchedIcon>),
+}
 
-    // Filter to only configured groups if specified
-    if config.proxy_groups.is_empty() {
-        return Ok((all_groups, node_delays));
+/// The next event to handle and the index of the controller it applies to.
+/// Tray events are for the controller shown in the menu.
+fn next_event(
+    tray_manager: &mut TrayManager,
+    notifier: &Notifier,
+    queued: &mut VecDeque<(String, TrayEvent)>,
+    controllers: &[Controller],
+    active: usize,
+) -> Option<(usize, TrayEvent)> {
+    if let Some(event) = tray_manager.poll_events() {
+        return Some((active, event));
     }
 
-    let filtered_groups: IndexMap<String, models::ProxyGroup> = all_groups
-        .into_iter()
-        .filter(|(name, _)| config.proxy_groups.contains(name))
+    // Notification buttons act on the controller the notification was about,
+    // even if the menu shows another one by now. Events for a controller that
+    // no longer exists are dropped.
+    while let Some((name, event)) = notifier.poll_action().or_else(|| queued.pop_front()) {
+        if let Some(index) = controllers.iter().position(|controller| controller.name == name) {
+            return Some((index, event));
+        }
+    }
+    None
+}
+
+/// Connects with edited settings without touching the running controllers.
+#[cfg(feature = "gtk-tray")]
+async fn test_settings_connection(controller_config: &config::ControllerConfig) -> Result<String> {
+    let clash_api = clash_api::ClashApi::new(controller_config)?;
+    clash_api.test_connection().await?;
+
+    Ok(match clash_api.version().await {
+        Ok(version) => format!("Connected to {} {}", version.flavour.name(), version.version),
+        Err(_) => "Connected".to_string(),
+    })
+}
+
+/// Validates and saves edited settings, then reconnects the edited controller
+/// so the new URL, secret and group filter apply immediately.
+#[cfg(feature = "gtk-tray")]
+async fn apply_settings(
+    values: &SettingsValues,
+    config: &mut Config,
+    controllers: &mut [Controller],
+    active: usize,
+) -> Result<()> {
+    let name = controllers[active].name.clone();
+    let mut edited = config.clone();
+    values.apply(&mut edited, &name);
+
+    let errors: Vec<String> = validate::validate(&edited, None)
+        .iter()
+        .filter(|problem| problem.is_error())
+        .map(ToString::to_string)
         .collect();
+    if !errors.is_empty() {
+        anyhow::bail!("{}", errors.join("\n"));
+    }
+
+    edited.save()?;
+    *config = edited;
 
-    Ok((filtered_groups, node_delays))
+    let controller_config = config.controller(Some(&name))?;
+    let mut controller = Controller::new(&controller_config, config)?;
+    match controller.connect(config).await {
+        Ok(_) => info!(controller = %name, "Reconnected with new settings"),
+        Err(e) => error!(controller = %name, "Failed to connect: {}", e),
+    }
+    controllers[active] = controller;
+
+    Ok(())
 }
 
-async fn handle_switch_node(
-    clash_api: &ClashApi,
+/// Rebuilds the menu for the active controller. The icon reflects the worst
+/// connection state across all controllers.
+fn update_tray(
     tray_manager: &mut TrayManager,
-    group: &str,
-    node: &str,
+    controllers: &[Controller],
+    active: usize,
+    config: &Config,
+    icon_cache: &IconCache,
+) -> Result<()> {
+    let controller = &controllers[active];
+
+    tray_manager.set_controllers(
+        controllers
+            .iter()
+            .map(|controller| (controller.name.clone(), controller.is_connected()))
+            .collect(),
+        active,
+    );
+    tray_manager.set_refresh_state(controller.last_refresh, controller.last_error.clone());
+    tray_manager.update_menu(&controller.proxy_groups, &controller.nodes, config, controller.supports_pinning(), icon_cache)?;
+    tray_manager.update_core_status(&controller.core_status)?;
+
+    if controllers.iter().all(Controller::is_connected) {
+        tray_manager.set_icon_connected()
+    } else {
+        tray_manager.set_icon_disconnected()
+    }
+}
+
+/// Downloads missing group icons in the background; the menu is rebuilt when they arrive.
+fn fetch_icons(
+    icon_cache: &mut IconCache,
+    proxy_groups: &IndexMap<String, ProxyGroup>,
+    background: &mpsc::UnboundedSender<Background>,
 ) {
-    match clash_api.switch_node(group, node).await {
+    if let Some(fetch) = icon_cache.fetch_missing(proxy_groups) {
+        let background = background.clone();
+        tokio::spawn(async move {
+            background.send(Background::Icons(fetch.await)).ok();
+        });
+    }
+}
+
+/// Starts the memory and log streams of a controller.
+fn start_streams(
+    controller: &Controller,
+    config: &Config,
+) -> (Option<watch::Receiver<Option<MemoryStats>>>, mpsc::Receiver<LogEntry>) {
+    let memory_stats = match controller.flavour() {
+        Some(flavour) if flavour.supports_memory() => controller.clash_api.stream_memory().ok(),
+        _ => None,
+    };
+    let log_receiver = controller.clash_api.stream_logs(&config.logs.level);
+
+    (memory_stats, log_receiver)
+}
+
+/// Notifies about a core that came back or whose Selectors were reset.
+fn report_refreshed(notifier: &mut Notifier, controller: &str, refreshed: &Refreshed) {
+    match refreshed {
+        Refreshed::Unchanged => {}
+        // A core that was down may have lost its selections
+        Refreshed::Reconnected(report) => report_restore(notifier, controller, report),
+        // Could be a quick restart or the user picking the first nodes, so ask
+        Refreshed::SelectorsReset => notifier.show_with_action(
+            NotificationKind::Reconnect,
+            "Selections Reset",
+            &format!("Groups on {} are back on their first nodes; the core may have restarted", controller),
+            "Restore",
+            controller,
+            TrayEvent::RestoreSelections,
+        ),
+    }
+}
+
+/// Notifies about selections re-applied after a reconnect or reload.
+fn report_restore(notifier: &mut Notifier, controller: &str, report: &SwitchReport) {
+    if !report.switched.is_empty() {
+        info!(%controller, "Restored selections: {}", report.switched_lines().join(", "));
+        notifier.show(NotificationKind::Reconnect, "Selections Restored", &report.switched_lines().join("\n"));
+    }
+    if !report.failed.is_empty() {
+        warn!(%controller, "Could not restore selections: {}", report.failed.join(", "));
+        notifier.show(NotificationKind::Error, "Restore Incomplete", &report.failed.join("\n"));
+    }
+}
+
+async fn refresh_core_config(controller: &Controller, tray_manager: &mut TrayManager) {
+    match controller.clash_api.get_configs().await {
+        Ok(core_config) => tray_manager.update_core_config(core_config),
+        Err(e) => warn!("Failed to fetch core config: {}", e),
+    }
+}
+
+/// Switches a node, offering "Undo" back to the previous node, or "Retry" on failure.
+async fn handle_switch_node(controller: &mut Controller, notifier: &mut Notifier, group: &str, node: &str) {
+    let previous = controller.proxy_groups.get(group).and_then(|group| group.now.clone());
+
+    match controller.clash_api.switch_node(group, node).await {
         Ok(_) => {
-            println!("Successfully switched {} to {}", group, node);
-            notify_success(
-                "Node Switched",
-                &format!("Switched {} to {}", group, node),
-            );
-            tray_manager.set_icon_connected().ok();
+            info!(%group, %node, "Switched node");
+            if let Err(e) = controller.selections.remember(group, node) {
+                warn!("Failed to remember selection: {}", e);
+            }
+            let summary = "Node Switched";
+            let body = format!("Switched {} to {}", group, node);
+            match previous.filter(|previous| previous != node) {
+                Some(previous) => notifier.show_with_action(
+                    NotificationKind::Switch,
+                    summary,
+                    &body,
+                    "Undo",
+                    &controller.name,
+                    TrayEvent::SwitchNode {
+                        group: group.to_string(),
+                        node: previous,
+                    },
+                ),
+                None => notifier.show(NotificationKind::Switch, summary, &body),
+            }
         }
         Err(e) => {
-            eprintln!("Failed to switch node: {}", e);
-            notify_error(
+            error!(%group, %node, "Failed to switch node: {}", e);
+            notifier.show_with_action(
+                NotificationKind::Error,
                 "Switch Failed",
                 &format!("Failed to switch {}: {}", group, e),
+                "Retry",
+                &controller.name,
+                TrayEvent::SwitchNode {
+                    group: group.to_string(),
+                    node: node.to_string(),
+                },
             );
-            tray_manager.set_icon_disconnected().ok();
         }
     }
 }
-
-fn notify_success(summary: &str, body: &str) {
-    Notification::new()
-        .summary(summary)
-        .body(body)
-        .timeout(3000)
-        .show()
-        .ok();
-}
-
-fn notify_error(summary: &str, body: &str) {
-    Notification::new()
-        .summary(summary)
-        .body(body)
-        .urgency(notify_rust::Urgency::Critical)
-        .timeout(5000)
-        .show()
-        .ok();
-}
@@ -1,13 +1,107 @@
 use serde::{Deserialize, Serialize};
 use indexmap::IndexMap;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub enum GroupType {
+    Selector,
+    URLTest,
+    Fallback,
+    LoadBalance,
+    Relay,
+    Smart,
+    Other(String),
+}
+
+impl GroupType {
+    /// Groups whose node is picked automatically by the core.
+    pub fn is_auto(&self) -> bool {
+        matches!(self, GroupType::URLTest | GroupType::Fallback | GroupType::Smart)
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            GroupType::Selector => "Selector",
+            GroupType::URLTest => "URLTest",
+            GroupType::Fallback => "Fallback",
+            GroupType::LoadBalance => "LoadBalance",
+            GroupType::Relay => "Relay",
+            GroupType::Smart => "Smart",
+            GroupType::Other(name) => name,
+        }
+    }
+}
+
+impl From<String> for GroupType {
+    fn from(name: String) -> Self {
+        match name.as_str() {
+            "Selector" => GroupType::Selector,
+            "URLTest" => GroupType::URLTest,
+            "Fallback" => GroupType::Fallback,
+            "LoadBalance" => GroupType::LoadBalance,
+            "Relay" => GroupType::Relay,
+            "Smart" => GroupType::Smart,
+            _ => GroupType::Other(name),
+        }
+    }
+}
+
+impl From<GroupType> for String {
+    fn from(group_type: GroupType) -> Self {
+        group_type.as_str().to_string()
+    }
+}
+
+impl fmt::Display for GroupType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Built-in outbounds that appear as group members but are not proxies, so
+/// `clbar fastest` and the failover watchdog never pick them.
+pub const BUILTIN_NODES: [&str; 3] = ["DIRECT", "REJECT", "REJECT-DROP"];
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ProxyGroup {
     pub name: String,
     #[serde(rename = "type")]
-    pub group_type: String,
+    pub group_type: GroupType,
     pub now: Option<String>,
     pub all: Option<Vec<String>>,
+    pub fixed: Option<String>,
+    pub strategy: Option<String>,
+    #[serde(default)]
+    pub hidden: bool,
+    pub icon: Option<String>,
+}
+
+impl ProxyGroup {
+    pub fn is_auto(&self) -> bool {
+        self.group_type.is_auto()
+    }
+
+    /// A group on `now` with the given members, for tests.
+    #[cfg(test)]
+    pub fn test(name: &str, group_type: GroupType, now: &str, all: &[&str]) -> Self {
+        ProxyGroup {
+            name: name.to_string(),
+            group_type,
+            now: Some(now.to_string()),
+            all: Some(all.iter().map(|node| node.to_string()).collect()),
+            fixed: None,
+            strategy: None,
+            hidden: false,
+            icon: None,
+        }
+    }
+}
+
+/// Groups keyed by name, as `ClashApi::get_proxies` returns them, for tests.
+#[cfg(test)]
+pub fn test_groups(groups: Vec<ProxyGroup>) -> IndexMap<String, ProxyGroup> {
+    groups.into_iter().map(|group| (group.name.clone(), group)).collect()
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -20,6 +114,53 @@ pub struct Proxy {
     pub now: Option<String>,
     pub all: Option<Vec<String>>,
     pub udp: Option<bool>,
+    pub fixed: Option<String>,
+    pub strategy: Option<String>,
+    #[serde(default)]
+    pub hidden: bool,
+    pub icon: Option<String>,
+    pub xudp: Option<bool>,
+    pub tfo: Option<bool>,
+    #[serde(rename = "provider-name")]
+    pub provider_name: Option<String>,
+    #[serde(rename = "dialer-proxy")]
+    pub dialer_proxy: Option<String>,
+}
+
+/// Everything clbar knows about a single proxy, keyed by name in the node catalog.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeInfo {
+    pub name: String,
+    pub node_type: String,
+    pub delay: Option<u32>,
+    pub udp: bool,
+    pub xudp: bool,
+    pub tfo: bool,
+    pub provider: Option<String>,
+    pub dialer_proxy: Option<String>,
+}
+
+impl NodeInfo {
+    /// Compact label suffix such as "[vless·UDP·TFO]".
+    pub fn badge(&self) -> String {
+        let mut parts = vec![self.node_type.to_lowercase()];
+        if self.udp {
+            parts.push("UDP".to_string());
+        }
+        if self.xudp {
+            parts.push("xUDP".to_string());
+        }
+        if self.tfo {
+            parts.push("TFO".to_string());
+        }
+        if let Some(ref provider) = self.provider {
+            parts.push(format!("@{}", provider));
+        }
+        if let Some(ref dialer) = self.dialer_proxy {
+            parts.push(format!("via {}", dialer));
+        }
+        format!("[{}]", parts.join("·"))
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -38,9 +179,222 @@ pub struct SwitchRequest {
     pub name: String,
 }
 
+/// Outcome of switching several groups at once.
+#[derive(Debug, Default)]
+pub struct SwitchReport {
+    /// (group, node) pairs now in effect
+    pub switched: Vec<(String, String)>,
+    pub failed: Vec<String>,
+}
+
+impl SwitchReport {
+    pub fn switched_lines(&self) -> Vec<String> {
+        self.switched
+            .iter()
+            .map(|(group, node)| format!("{} → {}", group, node))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DelayResponse {
+    pub delay: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VersionResponse {
+    pub version: String,
+    #[serde(default)]
+    pub meta: bool,
+    #[serde(default)]
+    pub premium: bool,
+}
+
+/// The Clash-compatible core behind the external controller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoreFlavour {
+    Clash,
+    ClashPremium,
+    Mihomo,
+    SingBox,
+}
+
+impl CoreFlavour {
+    pub fn detect(version: &VersionResponse) -> Self {
+        // sing-box reports both `meta` and `premium`, but prefixes its version
+        if version.version.starts_with("sing-box") {
+            CoreFlavour::SingBox
+        } else if version.meta {
+            CoreFlavour::Mihomo
+        } else if version.premium {
+            CoreFlavour::ClashPremium
+        } else {
+            CoreFlavour::Clash
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CoreFlavour::Clash => "Clash",
+            CoreFlavour::ClashPremium => "Clash Premium",
+            CoreFlavour::Mihomo => "mihomo",
+            CoreFlavour::SingBox => "sing-box",
+        }
+    }
+
+    /// Pinning URLTest/Fallback groups via `PUT /proxies` and `DELETE /group`.
+    pub fn supports_pinning(&self) -> bool {
+        *self == CoreFlavour::Mihomo
+    }
+
+    pub fn supports_memory(&self) -> bool {
+        matches!(self, CoreFlavour::Mihomo | CoreFlavour::SingBox)
+    }
+
+    /// `POST /restart` and `POST /configs/geo` are mihomo extensions.
+    pub fn supports_restart(&self) -> bool {
+        *self == CoreFlavour::Mihomo
+    }
+
+    pub fn supports_geo_update(&self) -> bool {
+        *self == CoreFlavour::Mihomo
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CoreVersion {
+    pub flavour: CoreFlavour,
+    pub version: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MemoryStats {
+    pub inuse: u64,
+    #[serde(default)]
+    pub oslimit: u64,
+}
+
+/// Runtime settings reported by `GET /configs`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CoreConfig {
+    #[serde(default)]
+    pub port: u16,
+    #[serde(default)]
+    pub socks_port: u16,
+    #[serde(default)]
+    pub mixed_port: u16,
+    #[serde(default)]
+    pub allow_lan: bool,
+    #[serde(default)]
+    pub ipv6: bool,
+    #[serde(default)]
+    pub mode: String,
+    #[serde(default)]
+    pub log_level: String,
+    pub tun: Option<TunConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TunConfig {
+    #[serde(default)]
+    pub enable: bool,
+}
+
+/// Partial update sent with `PATCH /configs`; unset fields are left alone.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CoreConfigPatch {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mixed_port: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allow_lan: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ipv6: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_level: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tun: Option<TunConfig>,
+}
+
+impl CoreConfigPatch {
+    /// Short description for notifications, e.g. "TUN on".
+    pub fn describe(&self) -> String {
+        let on_off = |enabled: bool| if enabled { "on" } else { "off" };
+        let mut changes = Vec::new();
+        if let Some(ref tun) = self.tun {
+            changes.push(format!("TUN {}", on_off(tun.enable)));
+        }
+        if let Some(allow_lan) = self.allow_lan {
+            changes.push(format!("Allow LAN {}", on_off(allow_lan)));
+        }
+        if let Some(ipv6) = self.ipv6 {
+            changes.push(format!("IPv6 {}", on_off(ipv6)));
+        }
+        if let Some(ref log_level) = self.log_level {
+            changes.push(format!("log level {}", log_level));
+        }
+        if let Some(ref mode) = self.mode {
+            changes.push(format!("mode {}", mode));
+        }
+        if let Some(mixed_port) = self.mixed_port {
+            changes.push(format!("mixed port {}", mixed_port));
+        }
+        changes.join(", ")
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReloadRequest {
+    pub path: String,
+    pub payload: String,
+}
+
+/// Error body returned by the core, e.g. `{"message": "..."}`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorResponse {
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogEntry {
+    #[serde(rename = "type")]
+    pub level: String,
+    pub payload: String,
+}
+
+/// What the "Core" submenu and tooltip show.
+#[derive(Debug, Clone)]
+pub struct CoreStatus {
+    pub version: Option<CoreVersion>,
+    pub memory: Option<MemoryStats>,
+    /// When clbar last connected to the core; the core's own start time is not exposed
+    pub connected_since: Option<std::time::Instant>,
+}
+
 #[derive(Debug, Clone)]
 pub enum TrayEvent {
     SwitchNode { group: String, node: String },
+    /// Move the hotkey target group `step` nodes along
+    CycleNode { step: isize },
+    /// Switch the hotkey target group to its lowest-delay node
+    FastestNode,
+    /// Choose a node of the hotkey target group with the picker
+    PickNode,
+    UnpinGroup { group: String },
+    PatchCoreConfig(CoreConfigPatch),
+    ReloadConfig,
+    RestartCore,
+    UpdateGeo,
+    ApplyPreset { name: String },
+    /// Switch groups back to their remembered nodes
+    RestoreSelections,
+    SelectController { name: String },
+    SavePreset,
+    #[cfg(feature = "gtk-tray")]
+    OpenSettings,
     Refresh,
     Quit,
 }
@@ -0,0 +1,138 @@
+use notify_rust::{Notification, Urgency};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::sync::mpsc;
+use std::time::Instant;
+use tracing::warn;
+
+use crate::config::{NotificationConfig, NotificationsConfig};
+use crate::models::TrayEvent;
+
+/// Kinds of tray notifications, each configured by `[notifications.<kind>]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NotificationKind {
+    /// A node, preset or pin changed on request
+    Switch,
+    /// A manual refresh finished
+    Refresh,
+    Error,
+    /// A controller came back and selections were restored
+    Reconnect,
+    /// The watchdog switched away from a failing node
+    Failover,
+    /// Other menu actions succeeded, e.g. a core reload or saved preset
+    Action,
+}
+
+/// Notifications with a button each keep a thread waiting until they close,
+/// e.g. forever with `timeout_ms = 0`; past this many, buttons are left out.
+const MAX_WAITING_ACTIONS: usize = 8;
+
+/// Shows notifications according to the config and collects the events of
+/// clicked buttons ("Undo", "Retry") for the main loop.
+pub struct Notifier {
+    config: NotificationsConfig,
+    last_shown: HashMap<NotificationKind, Instant>,
+    /// Threads currently waiting for a button click
+    waiting: Arc<AtomicUsize>,
+    sender: mpsc::Sender<(String, TrayEvent)>,
+    receiver: mpsc::Receiver<(String, TrayEvent)>,
+}
+
+impl Notifier {
+    pub fn new(config: &NotificationsConfig) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Notifier {
+            config: config.clone(),
+            last_shown: HashMap::new(),
+            waiting: Arc::new(AtomicUsize::new(0)),
+            sender,
+            receiver,
+        }
+    }
+
+    pub fn show(&mut self, kind: NotificationKind, summary: &str, body: &str) {
+        self.send(kind, summary, body, None);
+    }
+
+    /// Adds a button labelled `label` that hands `event` for `controller` to
+    /// the main loop when clicked.
+    pub fn show_with_action(
+        &mut self,
+        kind: NotificationKind,
+        summary: &str,
+        body: &str,
+        label: &str,
+        controller: &str,
+        event: TrayEvent,
+    ) {
+        self.send(kind, summary, body, Some((label, (controller.to_string(), event))));
+    }
+
+    /// The controller and event of a clicked notification button, if any.
+    pub fn poll_action(&self) -> Option<(String, TrayEvent)> {
+        self.receiver.try_recv().ok()
+    }
+
+    fn send(&mut self, kind: NotificationKind, summary: &str, body: &str, action: Option<(&str, (String, TrayEvent))>) {
+        let settings = self.settings(kind).clone();
+        if !settings.enabled {
+            return;
+        }
+
+        // Rate limit per kind; suppressed notifications are dropped, not queued
+        if let Some(shown) = self.last_shown.get(&kind) {
+            if shown.elapsed().as_secs() < settings.min_interval_secs {
+                return;
+            }
+        }
+        self.last_shown.insert(kind, Instant::now());
+
+        let action = action.filter(|_| self.waiting.load(Ordering::SeqCst) < MAX_WAITING_ACTIONS);
+
+        let mut notification = Notification::new();
+        notification
+            .summary(summary)
+            .body(body)
+            .urgency(Urgency::try_from(settings.urgency.as_str()).unwrap_or(Urgency::Normal))
+            .timeout(i32::try_from(settings.timeout_ms).unwrap_or(i32::MAX));
+        if let Some((label, _)) = action {
+            notification.action("clbar-action", label);
+        }
+
+        let handle = match notification.show() {
+            Ok(handle) => handle,
+            Err(e) => {
+                warn!("Failed to show notification: {}", e);
+                return;
+            }
+        };
+
+        // Waiting for a click blocks until the notification closes
+        if let Some((_, event)) = action {
+            let sender = self.sender.clone();
+            let waiting = Arc::clone(&self.waiting);
+            waiting.fetch_add(1, Ordering::SeqCst);
+            std::thread::spawn(move || {
+                handle.wait_for_action(|action| {
+                    if action == "clbar-action" {
+                        sender.send(event).ok();
+                    }
+                });
+                waiting.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+    }
+
+    fn settings(&self, kind: NotificationKind) -> &NotificationConfig {
+        match kind {
+            NotificationKind::Switch => &self.config.switch,
+            NotificationKind::Refresh => &self.config.refresh,
+            NotificationKind::Error => &self.config.error,
+            NotificationKind::Reconnect => &self.config.reconnect,
+            NotificationKind::Failover => &self.config.failover,
+            NotificationKind::Action => &self.config.action,
+        }
+    }
+}
@@ -0,0 +1,166 @@
+use indexmap::IndexMap;
+use regex::Regex;
+use tokio::task::JoinSet;
+
+use crate::clash_api::ClashApi;
+use crate::config::Preset;
+use crate::models::{GroupType, ProxyGroup, SwitchReport};
+
+/// Resolves each group of a preset to a concrete node. Targets are matched
+/// exactly first and otherwise treated as a regex picking the first member.
+pub fn resolve(
+    preset: &Preset,
+    proxy_groups: &IndexMap<String, ProxyGroup>,
+) -> (Vec<(String, String)>, Vec<String>) {
+    let mut switches = Vec::new();
+    let mut errors = Vec::new();
+
+    for (group_name, target) in &preset.groups {
+        let Some(members) = proxy_groups.get(group_name).and_then(|group| group.all.as_ref()) else {
+            errors.push(format!("{}: group not found", group_name));
+            continue;
+        };
+
+        if members.contains(target) {
+            switches.push((group_name.clone(), target.clone()));
+            continue;
+        }
+
+        let pattern = match Regex::new(target) {
+            Ok(pattern) => pattern,
+            Err(e) => {
+                errors.push(format!("{}: invalid pattern {}: {}", group_name, target, e));
+                continue;
+            }
+        };
+
+        match members.iter().find(|member| pattern.is_match(member)) {
+            Some(node) => switches.push((group_name.clone(), node.clone())),
+            None => errors.push(format!("{}: no node matches {}", group_name, target)),
+        }
+    }
+
+    (switches, errors)
+}
+
+/// Applies all switches of a preset in parallel.
+pub async fn apply(
+    clash_api: &ClashApi,
+    preset: &Preset,
+    proxy_groups: &IndexMap<String, ProxyGroup>,
+) -> SwitchReport {
+    let (switches, errors) = resolve(preset, proxy_groups);
+    let mut report = SwitchReport {
+        failed: errors,
+        ..SwitchReport::default()
+    };
+
+    let mut tasks = JoinSet::new();
+    for (group, node) in switches {
+        // Groups already on the target node need no request
+        if proxy_groups.get(&group).and_then(|g| g.now.as_ref()) == Some(&node) {
+            report.switched.push((group, node));
+            continue;
+        }
+
+        let clash_api = clash_api.clone();
+        tasks.spawn(async move {
+            let result = clash_api.switch_node(&group, &node).await;
+            (group, node, result)
+        });
+    }
+
+    while let Some(joined) = tasks.join_next().await {
+        match joined {
+            Ok((group, node, Ok(_))) => report.switched.push((group, node)),
+            Ok((group, _, Err(e))) => report.failed.push(format!("{}: {}", group, e)),
+            Err(e) => report.failed.push(format!("switch task failed: {}", e)),
+        }
+    }
+
+    report
+}
+
+/// Captures the current node of every Selector group as a new preset.
+pub fn from_current(name: &str, proxy_groups: &IndexMap<String, ProxyGroup>) -> Preset {
+    let groups = proxy_groups
+        .iter()
+        .filter(|(_, group)| group.group_type == GroupType::Selector)
+        .filter_map(|(group_name, group)| Some((group_name.clone(), group.now.clone()?)))
+        .collect();
+
+    Preset {
+        name: name.to_string(),
+        groups,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::test_groups;
+
+    fn proxy_groups() -> IndexMap<String, ProxyGroup> {
+        test_groups(vec![
+            ProxyGroup::test("PROXY", GroupType::Selector, "HK 01", &["HK 01", "HK 02", "JP 01", "US 01"]),
+            ProxyGroup::test("Streaming", GroupType::Selector, "US 01", &["US 01", "JP 01"]),
+            ProxyGroup::test("Auto", GroupType::URLTest, "JP 01", &["HK 01", "JP 01"]),
+        ])
+    }
+
+    fn preset(groups: &[(&str, &str)]) -> Preset {
+        Preset {
+            name: "test".to_string(),
+            groups: groups
+                .iter()
+                .map(|(group, target)| (group.to_string(), target.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn exact_names_win_over_patterns() {
+        let (switches, errors) = resolve(&preset(&[("PROXY", "JP 01")]), &proxy_groups());
+        assert_eq!(switches, [("PROXY".to_string(), "JP 01".to_string())]);
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn patterns_pick_the_first_matching_member() {
+        let (switches, errors) = resolve(&preset(&[("PROXY", "^HK"), ("Streaming", "JP|US")]), &proxy_groups());
+        assert_eq!(
+            switches,
+            [
+                ("PROXY".to_string(), "HK 01".to_string()),
+                ("Streaming".to_string(), "US 01".to_string()),
+            ]
+        );
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn unresolved_groups_are_reported_and_the_rest_still_switch() {
+        let (switches, errors) = resolve(
+            &preset(&[("Gone", "HK 01"), ("PROXY", "SG"), ("Streaming", "("), ("Auto", "HK")]),
+            &proxy_groups(),
+        );
+        assert_eq!(switches, [("Auto".to_string(), "HK 01".to_string())]);
+        assert_eq!(errors.len(), 3);
+        assert_eq!(errors[0], "Gone: group not found");
+        assert_eq!(errors[1], "PROXY: no node matches SG");
+        assert!(errors[2].starts_with("Streaming: invalid pattern ("));
+    }
+
+    #[test]
+    fn from_current_captures_selector_groups_only() {
+        let preset = from_current("now", &proxy_groups());
+        assert_eq!(preset.name, "now");
+        assert_eq!(
+            preset.groups.into_iter().collect::<Vec<_>>(),
+            [
+                ("PROXY".to_string(), "HK 01".to_string()),
+                ("Streaming".to_string(), "US 01".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,102 @@
+use anyhow::{Context, Result};
+use std::fmt;
+use std::fs;
+use std::ops::Deref;
+use std::os::unix::fs::PermissionsExt;
+use std::process::Command;
+
+use crate::config::ControllerConfig;
+
+/// A controller secret loaded at runtime. It lives only in memory and never
+/// shows up in `Debug` output.
+#[derive(Clone, Default)]
+pub struct Secret(String);
+
+impl Secret {
+    /// Resolves the secret for a controller. Sources are tried in order: the
+    /// environment variable, the secret file, the secret command, and finally
+    /// the plain `secret` value from the config.
+    pub fn load(controller: &ControllerConfig) -> Result<Self> {
+        if !controller.secret_env.is_empty() {
+            if let Ok(value) = std::env::var(&controller.secret_env) {
+                if !value.is_empty() {
+                    return Ok(Secret(value));
+                }
+            }
+        }
+
+        if !controller.secret_file.is_empty() {
+            return Self::read_file(&controller.secret_file).map(Secret);
+        }
+
+        if !controller.secret_command.is_empty() {
+            return Self::run_command(&controller.secret_command).map(Secret);
+        }
+
+        Ok(Secret(controller.secret.clone()))
+    }
+
+    /// Reads the first line of a secret file, refusing files other users can read.
+    fn read_file(path: &str) -> Result<String> {
+        let metadata = fs::metadata(path)
+            .with_context(|| format!("Failed to read secret file {}", path))?;
+
+        let mode = metadata.permissions().mode();
+        if mode & 0o077 != 0 {
+            anyhow::bail!(
+                "Secret file {} is accessible by other users (mode {:o}); run `chmod 600 {}`",
+                path,
+                mode & 0o777,
+                path
+            );
+        }
+
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read secret file {}", path))?;
+
+        Ok(content.lines().next().unwrap_or_default().trim().to_string())
+    }
+
+    /// Runs a command such as `pass show clash` and takes the first line of its output.
+    fn run_command(command: &str) -> Result<String> {
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .output()
+            .with_context(|| format!("Failed to run secret command: {}", command))?;
+
+        if !output.status.success() {
+            anyhow::bail!(
+                "Secret command `{}` failed ({}): {}",
+                command,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        let stdout = String::from_utf8(output.stdout).context("Secret command printed invalid UTF-8")?;
+        Ok(stdout.lines().next().unwrap_or_default().trim().to_string())
+    }
+
+    /// Replaces every occurrence of the secret in `text`.
+    pub fn redact(&self, text: &str) -> String {
+        if self.0.is_empty() {
+            return text.to_string();
+        }
+        text.replace(&self.0, "[redacted]")
+    }
+}
+
+impl Deref for Secret {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret([redacted])")
+    }
+}
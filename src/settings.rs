@@ -0,0 +1,290 @@
+use gtk::prelude::*;
+use std::sync::mpsc;
+
+use crate::config::{Config, ControllerConfig};
+
+/// Values edited in the settings window.
+#[derive(Debug, Clone)]
+pub struct SettingsValues {
+    pub url: String,
+    /// None when the secret comes from a file, command or environment variable
+    pub secret: Option<String>,
+    pub proxy_groups: Vec<String>,
+    pub refresh_interval_secs: u64,
+    pub delay_test_url: String,
+    pub delay_test_timeout: u32,
+    pub delay_green_threshold: u32,
+    pub delay_yellow_threshold: u32,
+}
+
+impl SettingsValues {
+    /// Writes the values into `config`, editing the named controller's URL and secret.
+    pub fn apply(&self, config: &mut Config, controller: &str) {
+        match config.controllers.iter_mut().find(|entry| entry.name == controller) {
+            Some(entry) => {
+                entry.url = self.url.clone();
+                if let Some(ref secret) = self.secret {
+                    entry.secret = secret.clone();
+                }
+            }
+            None => {
+                config.clash_api_url = self.url.clone();
+                if let Some(ref secret) = self.secret {
+                    config.clash_secret = secret.clone();
+                }
+            }
+        }
+
+        config.proxy_groups = self.proxy_groups.clone();
+        config.refresh_interval_secs = self.refresh_interval_secs;
+        config.delay_test_url = self.delay_test_url.clone();
+        config.delay_test_timeout = self.delay_test_timeout;
+        config.delay_green_threshold = self.delay_green_threshold;
+        config.delay_yellow_threshold = self.delay_yellow_threshold;
+    }
+
+    /// A copy of `controller` using the edited URL and secret.
+    pub fn controller_config(&self, controller: &ControllerConfig) -> ControllerConfig {
+        let mut controller = controller.clone();
+        controller.url = self.url.clone();
+        if let Some(ref secret) = self.secret {
+            controller.secret = secret.clone();
+        }
+        controller
+    }
+}
+
+pub enum SettingsEvent {
+    TestConnection(SettingsValues),
+    Save(SettingsValues),
+    /// Text for the status line, e.g. the result of a connection test
+    Status(String),
+    Closed,
+}
+
+/// Input widgets of the form; GTK widgets are reference-counted, so clones
+/// share the same widgets.
+#[derive(Clone)]
+struct Form {
+    url: gtk::Entry,
+    secret: Option<gtk::Entry>,
+    groups: Vec<(String, gtk::CheckButton)>,
+    refresh_interval: gtk::SpinButton,
+    delay_test_url: gtk::Entry,
+    delay_test_timeout: gtk::SpinButton,
+    green_threshold: gtk::SpinButton,
+    yellow_threshold: gtk::SpinButton,
+}
+
+impl Form {
+    fn values(&self) -> SettingsValues {
+        SettingsValues {
+            url: self.url.text().trim().to_string(),
+            secret: self.secret.as_ref().map(|entry| entry.text().to_string()),
+            proxy_groups: self
+                .groups
+                .iter()
+                .filter(|(_, check)| check.is_active())
+                .map(|(name, _)| name.clone())
+                .collect(),
+            refresh_interval_secs: self.refresh_interval.value_as_int() as u64,
+            delay_test_url: self.delay_test_url.text().trim().to_string(),
+            delay_test_timeout: self.delay_test_timeout.value_as_int() as u32,
+            delay_green_threshold: self.green_threshold.value_as_int() as u32,
+            delay_yellow_threshold: self.yellow_threshold.value_as_int() as u32,
+        }
+    }
+}
+
+/// The "Settings…" window. It runs on the GTK loop that `main` already pumps
+/// and reports button presses through `poll`.
+pub struct SettingsWindow {
+    window: gtk::Window,
+    status: gtk::Label,
+    sender: mpsc::Sender<SettingsEvent>,
+    receiver: mpsc::Receiver<SettingsEvent>,
+}
+
+impl SettingsWindow {
+    /// Opens the window for `controller`. `live_groups` and the configured
+    /// groups are offered in the group filter; none checked means every group
+    /// is shown.
+    pub fn open(config: &Config, controller: &ControllerConfig, live_groups: &[String]) -> Self {
+        let (sender, receiver) = mpsc::channel();
+
+        let window = gtk::Window::new(gtk::WindowType::Toplevel);
+        window.set_title(&format!("clbar settings ({})", controller.name));
+        window.set_default_size(420, 560);
+        window.set_border_width(12);
+
+        let grid = gtk::Grid::new();
+        grid.set_row_spacing(6);
+        grid.set_column_spacing(12);
+        let mut row = 0;
+
+        let url = gtk::Entry::new();
+        url.set_text(&controller.url);
+        url.set_hexpand(true);
+        attach(&grid, &mut row, "Controller URL", &url);
+
+        // A secret loaded from elsewhere is not shown and cannot be edited here
+        let secret_elsewhere = !controller.secret_file.is_empty()
+            || !controller.secret_command.is_empty()
+            || (!controller.secret_env.is_empty()
+                && std::env::var(&controller.secret_env).is_ok_and(|value| !value.is_empty()));
+        let secret = if secret_elsewhere {
+            let label = gtk::Label::new(Some("Loaded from file, command or environment"));
+            label.set_halign(gtk::Align::Start);
+            attach(&grid, &mut row, "Secret", &label);
+            None
+        } else {
+            let entry = gtk::Entry::new();
+            entry.set_visibility(false);
+            entry.set_text(&controller.secret);
+            attach(&grid, &mut row, "Secret", &entry);
+            Some(entry)
+        };
+
+        let refresh_interval = spin_button(config.refresh_interval_secs as f64);
+        attach(&grid, &mut row, "Refresh interval (s)", &refresh_interval);
+
+        let delay_test_url = gtk::Entry::new();
+        delay_test_url.set_text(&config.delay_test_url);
+        attach(&grid, &mut row, "Delay test URL", &delay_test_url);
+
+        let delay_test_timeout = spin_button(config.delay_test_timeout as f64);
+        attach(&grid, &mut row, "Delay test timeout (ms)", &delay_test_timeout);
+
+        let green_threshold = spin_button(config.delay_green_threshold as f64);
+        attach(&grid, &mut row, "Green below (ms)", &green_threshold);
+
+        let yellow_threshold = spin_button(config.delay_yellow_threshold as f64);
+        attach(&grid, &mut row, "Yellow below (ms)", &yellow_threshold);
+
+        let groups_label = gtk::Label::new(Some("Groups shown (none = all)"));
+        groups_label.set_halign(gtk::Align::Start);
+        grid.attach(&groups_label, 0, row, 2, 1);
+        row += 1;
+
+        let groups_box = gtk::Box::new(gtk::Orientation::Vertical, 2);
+        let mut groups = Vec::new();
+        for name in live_groups {
+            let check = gtk::CheckButton::with_label(name);
+            check.set_active(config.proxy_groups.contains(name));
+            groups_box.pack_start(&check, false, false, 0);
+            groups.push((name.clone(), check));
+        }
+        // Configured groups the core does not report right now (or while it is
+        // down) stay listed, so saving does not silently drop them
+        for name in config.proxy_groups.iter().filter(|name| !live_groups.contains(name)) {
+            let check = gtk::CheckButton::with_label(&format!("{} (not available now)", name));
+            check.set_active(true);
+            groups_box.pack_start(&check, false, false, 0);
+            groups.push((name.clone(), check));
+        }
+        let scrolled = gtk::ScrolledWindow::new(gtk::Adjustment::NONE, gtk::Adjustment::NONE);
+        scrolled.set_vexpand(true);
+        scrolled.set_min_content_height(160);
+        scrolled.add(&groups_box);
+        grid.attach(&scrolled, 0, row, 2, 1);
+        row += 1;
+
+        let status = gtk::Label::new(None);
+        status.set_halign(gtk::Align::Start);
+        status.set_line_wrap(true);
+        grid.attach(&status, 0, row, 2, 1);
+        row += 1;
+
+        let form = Form {
+            url,
+            secret,
+            groups,
+            refresh_interval,
+            delay_test_url,
+            delay_test_timeout,
+            green_threshold,
+            yellow_threshold,
+        };
+
+        let buttons = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+        let test_button = gtk::Button::with_label("Test connection");
+        let cancel_button = gtk::Button::with_label("Cancel");
+        let save_button = gtk::Button::with_label("Save");
+        buttons.pack_start(&test_button, false, false, 0);
+        buttons.pack_end(&save_button, false, false, 0);
+        buttons.pack_end(&cancel_button, false, false, 0);
+        grid.attach(&buttons, 0, row, 2, 1);
+
+        {
+            let form = form.clone();
+            let sender = sender.clone();
+            test_button.connect_clicked(move |_| {
+                sender.send(SettingsEvent::TestConnection(form.values())).ok();
+            });
+        }
+        {
+            let sender = sender.clone();
+            save_button.connect_clicked(move |_| {
+                sender.send(SettingsEvent::Save(form.values())).ok();
+            });
+        }
+        {
+            let window = window.clone();
+            cancel_button.connect_clicked(move |_| window.close());
+        }
+        {
+            let sender = sender.clone();
+            window.connect_delete_event(move |_, _| {
+                sender.send(SettingsEvent::Closed).ok();
+                gtk::glib::Propagation::Proceed
+            });
+        }
+
+        window.add(&grid);
+        window.show_all();
+
+        SettingsWindow {
+            window,
+            status,
+            sender,
+            receiver,
+        }
+    }
+
+    pub fn poll(&self) -> Option<SettingsEvent> {
+        self.receiver.try_recv().ok()
+    }
+
+    /// Lets background tasks report to the window, e.g. with `Status`.
+    pub fn sender(&self) -> mpsc::Sender<SettingsEvent> {
+        self.sender.clone()
+    }
+
+    pub fn set_status(&self, text: &str) {
+        self.status.set_text(text);
+    }
+
+    pub fn present(&self) {
+        self.window.present();
+    }
+
+    pub fn close(&self) {
+        self.window.close();
+    }
+}
+
+fn attach(grid: &gtk::Grid, row: &mut i32, label: &str, widget: &impl IsA<gtk::Widget>) {
+    let label = gtk::Label::new(Some(label));
+    label.set_halign(gtk::Align::Start);
+    grid.attach(&label, 0, *row, 1, 1);
+    grid.attach(widget, 1, *row, 1, 1);
+    *row += 1;
+}
+
+/// Accepts what `validate` does (at least 1) up to the largest value
+/// `value_as_int` can return, so saving does not clamp the config's values.
+fn spin_button(value: f64) -> gtk::SpinButton {
+    let spin = gtk::SpinButton::with_range(1.0, i32::MAX as f64, 1.0);
+    spin.set_value(value);
+    spin
+}
@@ -0,0 +1,504 @@
+//! Tray backend speaking StatusNotifierItem and `com.canonical.dbusmenu`
+//! directly over D-Bus, without GTK. The types mirror the parts of the
+//! tray-icon/muda API that `TrayManager` uses, so the same code builds the
+//! menu for either backend; `dbus` exports the resulting tree.
+
+mod dbus;
+
+use anyhow::Result;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::Cursor;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::{LazyLock, Mutex, MutexGuard};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tracing::error;
+
+pub mod menu {
+    pub use super::{
+        CheckMenuItem, IconMenuItem, Menu, MenuEvent, MenuId, MenuItem, PredefinedMenuItem, Submenu,
+    };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Standard,
+    Check,
+    Separator,
+    Submenu,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    kind: Kind,
+    label: String,
+    enabled: bool,
+    checked: bool,
+    /// PNG data, as dbusmenu expects for `icon-data`
+    icon: Option<Vec<u8>>,
+    children: Vec<i32>,
+}
+
+/// Everything the D-Bus side exports: the menu tree plus icon and tooltip.
+struct State {
+    next_id: i32,
+    entries: HashMap<i32, Entry>,
+    /// The menu passed to `TrayIcon::set_menu`; dbusmenu calls it id 0
+    root: Option<i32>,
+    revision: u32,
+    icon: Option<Icon>,
+    tooltip: String,
+    changes: Option<UnboundedSender<Change>>,
+}
+
+/// What the D-Bus task has to announce with a signal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Change {
+    Layout,
+    Icon,
+    ToolTip,
+}
+
+static STATE: LazyLock<Mutex<State>> = LazyLock::new(|| {
+    Mutex::new(State {
+        next_id: 1,
+        entries: HashMap::new(),
+        root: None,
+        revision: 1,
+        icon: None,
+        tooltip: String::new(),
+        changes: None,
+    })
+});
+
+fn state() -> MutexGuard<'static, State> {
+    STATE.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl State {
+    fn insert(&mut self, entry: Entry) -> MenuId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.entries.insert(id, entry);
+        MenuId(id)
+    }
+
+    fn update(&mut self, id: i32, apply: impl FnOnce(&mut Entry)) {
+        if let Some(entry) = self.entries.get_mut(&id) {
+            apply(entry);
+            self.changed(Change::Layout);
+        }
+    }
+
+    fn changed(&mut self, change: Change) {
+        if change == Change::Layout {
+            self.revision += 1;
+        }
+        if let Some(ref changes) = self.changes {
+            changes.send(change).ok();
+        }
+    }
+
+    /// Drops entries no longer reachable from the current menu. The menu is
+    /// rebuilt on every refresh, so this keeps the tree from growing.
+    fn collect_garbage(&mut self, keep: &[i32]) {
+        let mut reachable = HashSet::new();
+        let mut pending: Vec<i32> = keep.to_vec();
+        while let Some(id) = pending.pop() {
+            if reachable.insert(id) {
+                if let Some(entry) = self.entries.get(&id) {
+                    pending.extend(&entry.children);
+                }
+            }
+        }
+        self.entries.retain(|id, _| reachable.contains(id));
+    }
+}
+
+fn new_entry(kind: Kind, label: &str, enabled: bool) -> Entry {
+    Entry {
+        kind,
+        label: label.to_string(),
+        enabled,
+        checked: false,
+        icon: None,
+        children: Vec::new(),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MenuId(i32);
+
+/// Keyboard accelerators are not supported by dbusmenu hosts; kept for API parity.
+pub struct Accelerator;
+
+/// Anything that can be appended to a menu.
+pub trait IsMenuItem {
+    fn node(&self) -> i32;
+}
+
+fn append_to(parent: i32, item: &dyn IsMenuItem) -> Result<()> {
+    let child = item.node();
+    state().update(parent, |entry| entry.children.push(child));
+    Ok(())
+}
+
+#[derive(Clone)]
+pub struct Menu {
+    id: MenuId,
+}
+
+impl Menu {
+    pub fn new() -> Self {
+        Menu {
+            id: state().insert(new_entry(Kind::Submenu, "", true)),
+        }
+    }
+
+    pub fn append(&self, item: &dyn IsMenuItem) -> Result<()> {
+        append_to(self.id.0, item)
+    }
+}
+
+impl Default for Menu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone)]
+pub struct Submenu {
+    id: MenuId,
+}
+
+impl Submenu {
+    pub fn new(text: impl AsRef<str>, enabled: bool) -> Self {
+        Submenu {
+            id: state().insert(new_entry(Kind::Submenu, text.as_ref(), enabled)),
+        }
+    }
+
+    pub fn append(&self, item: &dyn IsMenuItem) -> Result<()> {
+        append_to(self.id.0, item)
+    }
+
+    pub fn remove_at(&self, position: usize) -> Option<MenuId> {
+        let mut state = state();
+        let entry = state.entries.get(&self.id.0)?;
+        if position >= entry.children.len() {
+            return None;
+        }
+
+        let mut removed = None;
+        state.update(self.id.0, |entry| removed = Some(entry.children.remove(position)));
+        removed.map(MenuId)
+    }
+}
+
+impl IsMenuItem for Submenu {
+    fn node(&self) -> i32 {
+        self.id.0
+    }
+}
+
+#[derive(Clone)]
+pub struct MenuItem {
+    id: MenuId,
+}
+
+impl MenuItem {
+    pub fn new(text: impl AsRef<str>, enabled: bool, _accelerator: Option<Accelerator>) -> Self {
+        MenuItem {
+            id: state().insert(new_entry(Kind::Standard, text.as_ref(), enabled)),
+        }
+    }
+
+    pub fn id(&self) -> &MenuId {
+        &self.id
+    }
+
+    pub fn set_text(&self, text: impl AsRef<str>) {
+        let text = text.as_ref().to_string();
+        state().update(self.id.0, |entry| entry.label = text);
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        state().update(self.id.0, |entry| entry.enabled = enabled);
+    }
+}
+
+impl IsMenuItem for MenuItem {
+    fn node(&self) -> i32 {
+        self.id.0
+    }
+}
+
+#[derive(Clone)]
+pub struct CheckMenuItem {
+    id: MenuId,
+}
+
+impl CheckMenuItem {
+    pub fn new(text: impl AsRef<str>, enabled: bool, checked: bool, _accelerator: Option<Accelerator>) -> Self {
+        let mut entry = new_entry(Kind::Check, text.as_ref(), enabled);
+        entry.checked = checked;
+        CheckMenuItem {
+            id: state().insert(entry),
+        }
+    }
+
+    pub fn id(&self) -> &MenuId {
+        &self.id
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        state().update(self.id.0, |entry| entry.enabled = enabled);
+    }
+
+    pub fn set_checked(&self, checked: bool) {
+        state().update(self.id.0, |entry| entry.checked = checked);
+    }
+}
+
+impl IsMenuItem for CheckMenuItem {
+    fn node(&self) -> i32 {
+        self.id.0
+    }
+}
+
+#[derive(Clone)]
+pub struct IconMenuItem {
+    id: MenuId,
+}
+
+impl IconMenuItem {
+    pub fn new(text: impl AsRef<str>, enabled: bool, icon: Option<Icon>, _accelerator: Option<Accelerator>) -> Self {
+        let mut entry = new_entry(Kind::Standard, text.as_ref(), enabled);
+        entry.icon = icon.and_then(|icon| icon.to_png());
+        IconMenuItem {
+            id: state().insert(entry),
+        }
+    }
+}
+
+impl IsMenuItem for IconMenuItem {
+    fn node(&self) -> i32 {
+        self.id.0
+    }
+}
+
+pub struct PredefinedMenuItem {
+    id: MenuId,
+}
+
+impl PredefinedMenuItem {
+    pub fn separator() -> Self {
+        PredefinedMenuItem {
+            id: state().insert(new_entry(Kind::Separator, "", true)),
+        }
+    }
+}
+
+impl IsMenuItem for PredefinedMenuItem {
+    fn node(&self) -> i32 {
+        self.id.0
+    }
+}
+
+/// An RGBA image used for the tray icon and menu item icons.
+#[derive(Debug, Clone)]
+pub struct Icon {
+    rgba: Vec<u8>,
+    width: u32,
+    height: u32,
+}
+
+#[derive(Debug)]
+pub struct BadIcon;
+
+impl fmt::Display for BadIcon {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("RGBA data does not match the icon size")
+    }
+}
+
+impl std::error::Error for BadIcon {}
+
+impl Icon {
+    pub fn from_rgba(rgba: Vec<u8>, width: u32, height: u32) -> Result<Self, BadIcon> {
+        if rgba.len() != (width * height * 4) as usize {
+            return Err(BadIcon);
+        }
+        Ok(Icon { rgba, width, height })
+    }
+
+    fn to_png(&self) -> Option<Vec<u8>> {
+        let image = image::RgbaImage::from_raw(self.width, self.height, self.rgba.clone())?;
+        let mut png = Cursor::new(Vec::new());
+        image.write_to(&mut png, image::ImageFormat::Png).ok()?;
+        Some(png.into_inner())
+    }
+
+    /// ARGB32 in network byte order, as `IconPixmap` expects.
+    fn to_argb(&self) -> Vec<u8> {
+        self.rgba
+            .chunks_exact(4)
+            .flat_map(|pixel| [pixel[3], pixel[0], pixel[1], pixel[2]])
+            .collect()
+    }
+}
+
+/// A clicked menu item.
+#[derive(Debug, Clone)]
+pub struct MenuEvent {
+    pub id: MenuId,
+}
+
+/// Clicks and scrolling on the icon itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrayIconEvent {
+    /// Left click
+    Activate,
+    /// Middle click
+    SecondaryActivate,
+    Scroll { delta: i32, horizontal: bool },
+}
+
+pub struct EventReceiver<T> {
+    receiver: Mutex<Receiver<T>>,
+}
+
+impl<T> EventReceiver<T> {
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.receiver
+            .lock()
+            .map_err(|_| TryRecvError::Disconnected)?
+            .try_recv()
+    }
+}
+
+struct Channel<T> {
+    sender: Mutex<Sender<T>>,
+    receiver: EventReceiver<T>,
+}
+
+impl<T> Channel<T> {
+    fn new() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Channel {
+            sender: Mutex::new(sender),
+            receiver: EventReceiver {
+                receiver: Mutex::new(receiver),
+            },
+        }
+    }
+
+    fn send(&self, event: T) {
+        if let Ok(sender) = self.sender.lock() {
+            sender.send(event).ok();
+        }
+    }
+}
+
+static MENU_EVENTS: LazyLock<Channel<MenuEvent>> = LazyLock::new(Channel::new);
+static ICON_EVENTS: LazyLock<Channel<TrayIconEvent>> = LazyLock::new(Channel::new);
+
+impl MenuEvent {
+    pub fn receiver() -> &'static EventReceiver<MenuEvent> {
+        &MENU_EVENTS.receiver
+    }
+}
+
+impl TrayIconEvent {
+    pub fn receiver() -> &'static EventReceiver<TrayIconEvent> {
+        &ICON_EVENTS.receiver
+    }
+}
+
+pub struct TrayIconBuilder {
+    menu: Option<Menu>,
+    tooltip: String,
+    icon: Option<Icon>,
+}
+
+impl TrayIconBuilder {
+    pub fn new() -> Self {
+        TrayIconBuilder {
+            menu: None,
+            tooltip: String::new(),
+            icon: None,
+        }
+    }
+
+    // Boxed like tray-icon's, so `TrayManager` builds for both backends
+    #[allow(clippy::boxed_local)]
+    pub fn with_menu(mut self, menu: Box<Menu>) -> Self {
+        self.menu = Some(*menu);
+        self
+    }
+
+    pub fn with_tooltip(mut self, tooltip: impl AsRef<str>) -> Self {
+        self.tooltip = tooltip.as_ref().to_string();
+        self
+    }
+
+    pub fn with_icon(mut self, icon: Icon) -> Self {
+        self.icon = Some(icon);
+        self
+    }
+
+    /// Starts serving the item on the session bus. Must be called from
+    /// within the Tokio runtime.
+    pub fn build(self) -> Result<TrayIcon> {
+        let (sender, receiver) = unbounded_channel();
+        {
+            let mut state = state();
+            state.root = self.menu.map(|menu| menu.id.0);
+            state.icon = self.icon;
+            state.tooltip = self.tooltip;
+            state.changes = Some(sender);
+        }
+
+        tokio::spawn(async move {
+            if let Err(e) = dbus::serve(receiver).await {
+                error!("StatusNotifierItem backend failed: {:#}", e);
+            }
+        });
+
+        Ok(TrayIcon { _private: () })
+    }
+}
+
+impl Default for TrayIconBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct TrayIcon {
+    _private: (),
+}
+
+impl TrayIcon {
+    pub fn set_menu(&self, menu: Option<Box<Menu>>) {
+        let mut state = state();
+        state.root = menu.map(|menu| menu.id.0);
+        let keep: Vec<i32> = state.root.into_iter().collect();
+        state.collect_garbage(&keep);
+        state.changed(Change::Layout);
+    }
+
+    pub fn set_tooltip(&self, tooltip: Option<impl AsRef<str>>) -> Result<()> {
+        let mut state = state();
+        state.tooltip = tooltip.map(|tooltip| tooltip.as_ref().to_string()).unwrap_or_default();
+        state.changed(Change::ToolTip);
+        Ok(())
+    }
+
+    pub fn set_icon(&self, icon: Option<Icon>) -> Result<()> {
+        let mut state = state();
+        state.icon = icon;
+        state.changed(Change::Icon);
+        Ok(())
+    }
+}
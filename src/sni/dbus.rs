@@ -0,0 +1,337 @@
+use anyhow::{Context, Result};
+use futures_util::StreamExt;
+use serde::Serialize;
+use std::collections::HashMap;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tracing::{info, warn};
+use zbus::object_server::SignalEmitter;
+use zbus::zvariant::{OwnedObjectPath, Type, Value};
+use zbus::{fdo, interface, Connection};
+
+use super::{state, Change, Entry, Kind, MenuEvent, MenuId, State, TrayIconEvent, ICON_EVENTS, MENU_EVENTS};
+
+const ITEM_PATH: &str = "/StatusNotifierItem";
+const MENU_PATH: &str = "/MenuBar";
+const WATCHER: &str = "org.kde.StatusNotifierWatcher";
+
+/// Exports the item and its menu, registers with the watcher and forwards
+/// changes from the tray as signals until the process exits.
+pub(super) async fn serve(mut changes: UnboundedReceiver<Change>) -> Result<()> {
+    let name = format!("org.kde.StatusNotifierItem-{}-1", std::process::id());
+    let connection = zbus::connection::Builder::session()?
+        .name(name.as_str())?
+        .serve_at(ITEM_PATH, StatusNotifierItem)?
+        .serve_at(MENU_PATH, DBusMenu)?
+        .build()
+        .await
+        .context("Failed to connect to the D-Bus session bus")?;
+
+    // The watcher may start after us or restart with the panel
+    let dbus = fdo::DBusProxy::new(&connection).await?;
+    let mut watcher_changes = dbus
+        .receive_name_owner_changed_with_args(&[(0, WATCHER)])
+        .await?;
+    if let Err(e) = register(&connection, &name).await {
+        info!("No StatusNotifierWatcher yet, waiting for one: {:#}", e);
+    }
+
+    let object_server = connection.object_server();
+    let item = object_server.interface::<_, StatusNotifierItem>(ITEM_PATH).await?;
+    let menu = object_server.interface::<_, DBusMenu>(MENU_PATH).await?;
+
+    loop {
+        tokio::select! {
+            change = changes.recv() => {
+                let result = match change {
+                    Some(Change::Layout) => {
+                        let revision = state().revision;
+                        DBusMenu::layout_updated(menu.signal_emitter(), revision, 0).await
+                    }
+                    Some(Change::Icon) => StatusNotifierItem::new_icon(item.signal_emitter()).await,
+                    Some(Change::ToolTip) => StatusNotifierItem::new_tool_tip(item.signal_emitter()).await,
+                    None => return Ok(()),
+                };
+                if let Err(e) = result {
+                    warn!("Failed to signal a tray change: {}", e);
+                }
+            }
+            Some(signal) = watcher_changes.next() => {
+                let owner_appeared = signal.args().is_ok_and(|args| args.new_owner().is_some());
+                if owner_appeared {
+                    if let Err(e) = register(&connection, &name).await {
+                        warn!("Failed to register with StatusNotifierWatcher: {:#}", e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+async fn register(connection: &Connection, name: &str) -> Result<()> {
+    connection
+        .call_method(
+            Some(WATCHER),
+            "/StatusNotifierWatcher",
+            Some(WATCHER),
+            "RegisterStatusNotifierItem",
+            &(name,),
+        )
+        .await?;
+    Ok(())
+}
+
+/// One image of an `IconPixmap`-style property: width, height, ARGB32 data.
+type Pixmap = (i32, i32, Vec<u8>);
+
+fn pixmaps(state: &State) -> Vec<Pixmap> {
+    state
+        .icon
+        .iter()
+        .map(|icon| (icon.width as i32, icon.height as i32, icon.to_argb()))
+        .collect()
+}
+
+struct StatusNotifierItem;
+
+#[interface(name = "org.kde.StatusNotifierItem")]
+impl StatusNotifierItem {
+    #[zbus(property)]
+    fn category(&self) -> &str {
+        "ApplicationStatus"
+    }
+
+    #[zbus(property)]
+    fn id(&self) -> &str {
+        "clbar"
+    }
+
+    #[zbus(property)]
+    fn title(&self) -> &str {
+        "Clash Node Switcher"
+    }
+
+    #[zbus(property)]
+    fn status(&self) -> &str {
+        "Active"
+    }
+
+    #[zbus(property)]
+    fn icon_name(&self) -> &str {
+        ""
+    }
+
+    #[zbus(property)]
+    fn icon_pixmap(&self) -> Vec<Pixmap> {
+        pixmaps(&state())
+    }
+
+    /// Icon name, icon pixmaps, title and body
+    #[zbus(property)]
+    fn tool_tip(&self) -> (String, Vec<Pixmap>, String, String) {
+        let state = state();
+        let (title, body) = state.tooltip.split_once('\n').unwrap_or((&state.tooltip, ""));
+        (String::new(), Vec::new(), title.to_string(), body.to_string())
+    }
+
+    /// Clicks open the menu only when asked to; clbar wants click actions.
+    #[zbus(property)]
+    fn item_is_menu(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn menu(&self) -> OwnedObjectPath {
+        OwnedObjectPath::try_from(MENU_PATH).expect("valid object path")
+    }
+
+    fn activate(&self, _x: i32, _y: i32) {
+        ICON_EVENTS.send(TrayIconEvent::Activate);
+    }
+
+    fn secondary_activate(&self, _x: i32, _y: i32) {
+        ICON_EVENTS.send(TrayIconEvent::SecondaryActivate);
+    }
+
+    fn scroll(&self, delta: i32, orientation: &str) {
+        ICON_EVENTS.send(TrayIconEvent::Scroll {
+            delta,
+            horizontal: orientation.eq_ignore_ascii_case("horizontal"),
+        });
+    }
+
+    /// Hosts show the exported menu themselves.
+    fn context_menu(&self, _x: i32, _y: i32) {}
+
+    #[zbus(signal)]
+    async fn new_icon(emitter: &SignalEmitter<'_>) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn new_tool_tip(emitter: &SignalEmitter<'_>) -> zbus::Result<()>;
+}
+
+/// A menu node as `GetLayout` returns it: `(ia{sv}av)`.
+#[derive(Serialize, Type, Value)]
+struct Layout {
+    id: i32,
+    properties: HashMap<String, Value<'static>>,
+    children: Vec<Value<'static>>,
+}
+
+/// The dbusmenu id of a node; the current root menu is always 0.
+fn menu_id(state: &State, id: i32) -> Option<i32> {
+    if id == 0 {
+        state.root
+    } else {
+        state.entries.contains_key(&id).then_some(id)
+    }
+}
+
+fn properties(entry: &Entry, names: &[String]) -> HashMap<String, Value<'static>> {
+    let mut properties: HashMap<String, Value<'static>> = HashMap::new();
+    match entry.kind {
+        Kind::Separator => {
+            properties.insert("type".into(), "separator".into());
+        }
+        _ => {
+            // dbusmenu reads underscores as mnemonics
+            properties.insert("label".into(), entry.label.replace('_', "__").into());
+            properties.insert("enabled".into(), entry.enabled.into());
+        }
+    }
+    if entry.kind == Kind::Check {
+        properties.insert("toggle-type".into(), "checkmark".into());
+        properties.insert("toggle-state".into(), i32::from(entry.checked).into());
+    }
+    if entry.kind == Kind::Submenu {
+        properties.insert("children-display".into(), "submenu".into());
+    }
+    if let Some(ref icon) = entry.icon {
+        properties.insert("icon-data".into(), icon.clone().into());
+    }
+
+    if !names.is_empty() {
+        properties.retain(|name, _| names.contains(name));
+    }
+    properties
+}
+
+fn layout(state: &State, id: i32, depth: i32, names: &[String]) -> Option<Layout> {
+    let entry = state.entries.get(&menu_id(state, id)?)?;
+    let children = if depth == 0 {
+        Vec::new()
+    } else {
+        entry
+            .children
+            .iter()
+            .filter_map(|child| layout(state, *child, depth - 1, names))
+            .map(Value::from)
+            .collect()
+    };
+
+    Some(Layout {
+        id,
+        properties: properties(entry, names),
+        children,
+    })
+}
+
+struct DBusMenu;
+
+#[interface(name = "com.canonical.dbusmenu")]
+impl DBusMenu {
+    #[zbus(property)]
+    fn version(&self) -> u32 {
+        3
+    }
+
+    #[zbus(property)]
+    fn text_direction(&self) -> &str {
+        "ltr"
+    }
+
+    #[zbus(property)]
+    fn status(&self) -> &str {
+        "normal"
+    }
+
+    #[zbus(property)]
+    fn icon_theme_path(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    fn get_layout(&self, parent_id: i32, recursion_depth: i32, property_names: Vec<String>) -> fdo::Result<(u32, Layout)> {
+        let state = state();
+        let layout = layout(&state, parent_id, recursion_depth, &property_names)
+            .ok_or_else(|| fdo::Error::InvalidArgs(format!("Unknown menu item {}", parent_id)))?;
+        Ok((state.revision, layout))
+    }
+
+    fn get_group_properties(
+        &self,
+        ids: Vec<i32>,
+        property_names: Vec<String>,
+    ) -> Vec<(i32, HashMap<String, Value<'static>>)> {
+        let state = state();
+        ids.into_iter()
+            .filter_map(|id| {
+                let entry = state.entries.get(&menu_id(&state, id)?)?;
+                Some((id, properties(entry, &property_names)))
+            })
+            .collect()
+    }
+
+    fn get_property(&self, id: i32, name: String) -> fdo::Result<Value<'static>> {
+        let state = state();
+        menu_id(&state, id)
+            .and_then(|id| state.entries.get(&id))
+            .and_then(|entry| properties(entry, &[]).remove(&name))
+            .ok_or_else(|| fdo::Error::InvalidArgs(format!("No property {} on menu item {}", name, id)))
+    }
+
+    fn event(&self, id: i32, event_id: &str, _data: Value<'_>, _timestamp: u32) {
+        if event_id == "clicked" {
+            clicked(id);
+        }
+    }
+
+    fn event_group(&self, events: Vec<(i32, String, Value<'_>, u32)>) -> Vec<i32> {
+        let mut errors = Vec::new();
+        for (id, event_id, _, _) in events {
+            if !state().entries.contains_key(&id) {
+                errors.push(id);
+            } else if event_id == "clicked" {
+                clicked(id);
+            }
+        }
+        errors
+    }
+
+    /// The menu is kept up to date, so it never needs refreshing before showing.
+    fn about_to_show(&self, _id: i32) -> bool {
+        false
+    }
+
+    fn about_to_show_group(&self, _ids: Vec<i32>) -> (Vec<i32>, Vec<i32>) {
+        (Vec::new(), Vec::new())
+    }
+
+    #[zbus(signal)]
+    async fn layout_updated(emitter: &SignalEmitter<'_>, revision: u32, parent: i32) -> zbus::Result<()>;
+}
+
+/// Check items toggle themselves on click, as they do with GTK.
+fn clicked(id: i32) {
+    {
+        let mut state = state();
+        let Some(entry) = state.entries.get(&id) else {
+            return;
+        };
+        if !entry.enabled {
+            return;
+        }
+        if entry.kind == Kind::Check {
+            state.update(id, |entry| entry.checked = !entry.checked);
+        }
+    }
+    MENU_EVENTS.send(MenuEvent { id: MenuId(id) });
+}
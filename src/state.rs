@@ -0,0 +1,146 @@
+use anyhow::{Context, Result};
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::clash_api::ClashApi;
+use crate::config::DEFAULT_CONTROLLER;
+use crate::models::{ProxyGroup, SwitchReport};
+
+/// Last node chosen per group, persisted so selections survive a core
+/// restart or config reload.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Selections {
+    #[serde(skip)]
+    controller: String,
+
+    #[serde(default)]
+    pub groups: IndexMap<String, String>,
+}
+
+impl Selections {
+    /// Loads the selections remembered for a controller.
+    pub fn load(controller: &str) -> Result<Self> {
+        let state_path = Self::state_path(controller)?;
+
+        let mut selections = if state_path.exists() {
+            let content = fs::read_to_string(&state_path)
+                .context("Failed to read state file")?;
+
+            toml::from_str(&content).context("Failed to parse state file")?
+        } else {
+            Selections::default()
+        };
+
+        selections.controller = controller.to_string();
+        Ok(selections)
+    }
+
+    /// Empty selections for a controller, used when the state file is unreadable.
+    pub fn empty(controller: &str) -> Self {
+        Selections {
+            controller: controller.to_string(),
+            groups: IndexMap::new(),
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let state_path = Self::state_path(&self.controller)?;
+
+        if let Some(parent) = state_path.parent() {
+            fs::create_dir_all(parent)
+                .context("Failed to create state directory")?;
+        }
+
+        let content = toml::to_string_pretty(self)
+            .context("Failed to serialize state")?;
+
+        fs::write(&state_path, content)
+            .context("Failed to write state file")?;
+
+        Ok(())
+    }
+
+    pub fn remember(&mut self, group: &str, node: &str) -> Result<()> {
+        self.remember_all(&[(group.to_string(), node.to_string())])
+    }
+
+    pub fn remember_all(&mut self, switched: &[(String, String)]) -> Result<()> {
+        // Pick up selections written by other clbar processes (e.g. the CLI)
+        if let Ok(latest) = Self::load(&self.controller) {
+            *self = latest;
+        }
+        for (group, node) in switched {
+            self.groups.insert(group.clone(), node.clone());
+        }
+        self.save()
+    }
+
+    pub fn forget(&mut self, group: &str) -> Result<()> {
+        if self.groups.shift_remove(group).is_some() {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    /// Switches every group whose current node differs from the remembered
+    /// one, skipping nodes that no longer exist.
+    pub async fn restore(
+        &self,
+        clash_api: &ClashApi,
+        proxy_groups: &IndexMap<String, ProxyGroup>,
+    ) -> SwitchReport {
+        let mut report = SwitchReport::default();
+
+        for (group_name, node) in &self.groups {
+            let Some(group) = proxy_groups.get(group_name) else {
+                continue;
+            };
+            if group.now.as_ref() == Some(node) {
+                continue;
+            }
+
+            let exists = group.all.as_ref().is_some_and(|all| all.contains(node));
+            if !exists {
+                report.failed.push(format!("{}: {} no longer exists", group_name, node));
+                continue;
+            }
+
+            match clash_api.switch_node(group_name, node).await {
+                Ok(_) => report.switched.push((group_name.clone(), node.clone())),
+                Err(e) => report.failed.push(format!("{}: {}", group_name, e)),
+            }
+        }
+
+        report
+    }
+
+    fn state_path(controller: &str) -> Result<PathBuf> {
+        let state_dir = dirs::state_dir()
+            .or_else(dirs::data_local_dir)
+            .context("Failed to get state directory")?;
+
+        let file_name = if controller == DEFAULT_CONTROLLER {
+            "selections.toml".to_string()
+        } else {
+            format!("selections-{}.toml", file_name_part(controller))
+        };
+
+        Ok(state_dir.join("clbar").join(file_name))
+    }
+}
+
+/// Percent-encodes everything but `[A-Za-z0-9_-]`, so a name like "../x" or
+/// "a/b" cannot leave the state directory and distinct names stay distinct.
+fn file_name_part(name: &str) -> String {
+    name.bytes()
+        .map(|byte| {
+            if byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'-' {
+                (byte as char).to_string()
+            } else {
+                format!("%{:02X}", byte)
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,168 @@
+use anyhow::{Context, Result};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, Error, SignatureScheme};
+use std::sync::Arc;
+
+use crate::config::TlsConfig;
+
+/// Accepts exactly one server certificate, identified by its SHA-256
+/// fingerprint. Hostname and issuer are not checked, so self-signed
+/// certificates and SSH-forwarded ports work.
+#[derive(Debug)]
+struct PinnedVerifier {
+    fingerprint: Vec<u8>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        let digest = ring::digest::digest(&ring::digest::SHA256, end_entity.as_ref());
+        if digest.as_ref() == self.fingerprint.as_slice() {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(Error::General(format!(
+                "certificate fingerprint {} does not match the pinned one",
+                format_fingerprint(digest.as_ref())
+            )))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// Builds a rustls config that trusts only the pinned certificate, with the
+/// client certificate attached when one is configured.
+pub fn pinned_config(tls: &TlsConfig) -> Result<ClientConfig> {
+    let fingerprint = parse_fingerprint(&tls.fingerprint)?;
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .context("Failed to set up TLS")?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(PinnedVerifier { fingerprint, provider }));
+
+    if tls.client_cert.is_empty() {
+        return Ok(builder.with_no_client_auth());
+    }
+
+    let certs = CertificateDer::pem_file_iter(&tls.client_cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("Failed to read client certificate {}", tls.client_cert))?;
+    let key = PrivateKeyDer::from_pem_file(client_key_path(tls))
+        .with_context(|| format!("Failed to read client key {}", client_key_path(tls)))?;
+
+    builder
+        .with_client_auth_cert(certs, key)
+        .context("Invalid client certificate or key")
+}
+
+/// The client key file; the key may also be bundled with the certificate.
+fn client_key_path(tls: &TlsConfig) -> &str {
+    if tls.client_key.is_empty() {
+        &tls.client_cert
+    } else {
+        &tls.client_key
+    }
+}
+
+/// Accepts `AB:CD:...` as printed by `openssl x509 -fingerprint -sha256`, or plain hex.
+pub fn parse_fingerprint(fingerprint: &str) -> Result<Vec<u8>> {
+    let hex: String = fingerprint.chars().filter(|c| *c != ':' && !c.is_whitespace()).collect();
+    if hex.len() != 64 || !hex.is_ascii() {
+        anyhow::bail!("TLS fingerprint must be a SHA-256 hash: {}", fingerprint);
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&hex[i..i + 2], 16)
+                .with_context(|| format!("Invalid TLS fingerprint: {}", fingerprint))
+        })
+        .collect()
+}
+
+fn format_fingerprint(digest: &[u8]) -> String {
+    digest
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEX: &str = "0123456789abcdef0123456789ABCDEF0123456789abcdef0123456789ABCDEF";
+
+    #[test]
+    fn parses_plain_hex_in_either_case() {
+        let digest = parse_fingerprint(HEX).unwrap();
+        assert_eq!(digest.len(), 32);
+        assert_eq!(&digest[..8], &[0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef]);
+        assert_eq!(digest[..16], digest[16..]);
+    }
+
+    #[test]
+    fn parses_openssl_output_with_colons_and_whitespace() {
+        let formatted = format_fingerprint(&parse_fingerprint(HEX).unwrap());
+        assert_eq!(formatted.len(), 95);
+        assert_eq!(parse_fingerprint(&formatted).unwrap(), parse_fingerprint(HEX).unwrap());
+        assert_eq!(
+            parse_fingerprint(&format!("  {}\n", formatted.replace(':', ": "))).unwrap(),
+            parse_fingerprint(HEX).unwrap()
+        );
+    }
+
+    #[test]
+    fn rejects_wrong_lengths_and_non_hex() {
+        assert!(parse_fingerprint("").is_err());
+        assert!(parse_fingerprint(&HEX[..62]).is_err());
+        assert!(parse_fingerprint(&format!("{}00", HEX)).is_err());
+        assert!(parse_fingerprint(&HEX.replace('0', "g")).is_err());
+        // 32 two-byte characters are 64 bytes but not hex
+        assert!(parse_fingerprint(&"é".repeat(32)).is_err());
+    }
+
+    #[test]
+    fn client_key_defaults_to_the_certificate() {
+        let mut tls = TlsConfig {
+            client_cert: "client.pem".to_string(),
+            ..TlsConfig::default()
+        };
+        assert_eq!(client_key_path(&tls), "client.pem");
+        tls.client_key = "client.key".to_string();
+        assert_eq!(client_key_path(&tls), "client.key");
+    }
+}
@@ -0,0 +1,330 @@
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper::body::Incoming;
+use hyper_util::rt::TokioIo;
+use reqwest::header::{HeaderValue, AUTHORIZATION};
+use reqwest::{Certificate, Client, ClientBuilder, Identity, Method, Proxy, StatusCode};
+use serde::de::DeserializeOwned;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::net::UnixStream;
+use tracing::{debug, warn};
+
+use crate::config::ControllerConfig;
+use crate::secret::Secret;
+use crate::tls;
+
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How requests reach the external controller: plain HTTP(S) via reqwest, or
+/// HTTP/1.1 over mihomo's `external-controller-unix` socket.
+#[derive(Clone)]
+pub enum Transport {
+    Http {
+        client: Client,
+        stream_client: Client,
+        base_url: String,
+    },
+    Unix {
+        socket: PathBuf,
+    },
+}
+
+/// A controller response, independent of the transport it arrived on.
+pub struct ApiResponse {
+    status: StatusCode,
+    body: ResponseBody,
+}
+
+enum ResponseBody {
+    Http(reqwest::Response),
+    /// The deadline of the request's timeout, which also covers reading the body
+    Unix(Incoming, Option<tokio::time::Instant>),
+}
+
+impl Transport {
+    /// Parses `http(s)://host:port` or `unix:///path/to/socket`, applying the
+    /// controller's TLS and proxy settings to HTTP(S) clients.
+    pub fn new(controller: &ControllerConfig) -> Result<Self> {
+        let url = controller.url.as_str();
+        if let Some(socket) = url.strip_prefix("unix://") {
+            if socket.is_empty() {
+                anyhow::bail!("Unix socket URL has no path: {}", url);
+            }
+            return Ok(Transport::Unix {
+                socket: PathBuf::from(socket),
+            });
+        }
+
+        if controller.tls.insecure && controller.tls.fingerprint.is_empty() {
+            warn!(
+                controller = %controller.name,
+                "TLS certificate verification is disabled; anyone on the path can read your secret"
+            );
+        }
+
+        let client = Self::client_builder(controller)?
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .context("Failed to create HTTP client")?;
+
+        // Streaming endpoints stay open indefinitely, so no overall timeout
+        let stream_client = Self::client_builder(controller)?
+            .connect_timeout(REQUEST_TIMEOUT)
+            .build()
+            .context("Failed to create HTTP client")?;
+
+        Ok(Transport::Http {
+            client,
+            stream_client,
+            base_url: url.trim_end_matches('/').to_string(),
+        })
+    }
+
+    fn client_builder(controller: &ControllerConfig) -> Result<ClientBuilder> {
+        let settings = &controller.tls;
+        let mut builder = Client::builder();
+
+        if !controller.proxy.is_empty() {
+            let proxy = Proxy::all(&controller.proxy)
+                .with_context(|| format!("Invalid proxy URL: {}", controller.proxy))?;
+            builder = builder.proxy(proxy);
+        }
+
+        // A pinned fingerprint replaces all other certificate checks
+        if !settings.fingerprint.is_empty() {
+            return Ok(builder.use_preconfigured_tls(tls::pinned_config(settings)?));
+        }
+
+        if !settings.ca_cert.is_empty() {
+            let pem = fs::read(&settings.ca_cert)
+                .with_context(|| format!("Failed to read CA certificate {}", settings.ca_cert))?;
+            for certificate in Certificate::from_pem_bundle(&pem).context("Invalid CA certificate")? {
+                builder = builder.add_root_certificate(certificate);
+            }
+            builder = builder.use_rustls_tls();
+        }
+
+        if !settings.client_cert.is_empty() {
+            let mut pem = fs::read(&settings.client_cert)
+                .with_context(|| format!("Failed to read client certificate {}", settings.client_cert))?;
+            if !settings.client_key.is_empty() {
+                pem.extend(
+                    fs::read(&settings.client_key)
+                        .with_context(|| format!("Failed to read client key {}", settings.client_key))?,
+                );
+            }
+            let identity = Identity::from_pem(&pem).context("Invalid client certificate or key")?;
+            builder = builder.identity(identity).use_rustls_tls();
+        }
+
+        if settings.insecure {
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+
+        Ok(builder)
+    }
+
+    /// Sends a request to `path` (including any query string). `timeout`
+    /// overrides the default; streaming requests pass `None` to stay open.
+    /// Every request is logged at debug level with its status and timing.
+    pub async fn send(
+        &self,
+        method: Method,
+        path: &str,
+        secret: &Secret,
+        body: Option<&serde_json::Value>,
+        timeout: Option<Duration>,
+    ) -> Result<ApiResponse> {
+        let started = Instant::now();
+        let result = self.send_request(method.clone(), path, secret, body, timeout).await;
+        let elapsed_ms = started.elapsed().as_millis() as u64;
+
+        let url = self.redacted_url(path, secret);
+        match result {
+            Ok(ref response) => debug!(%method, %url, status = response.status.as_u16(), elapsed_ms, "Controller request"),
+            Err(ref e) => debug!(%method, %url, elapsed_ms, "Controller request failed: {}", secret.redact(&format!("{:#}", e))),
+        }
+        result
+    }
+
+    /// The request URL for logs, without the secret or URL credentials.
+    fn redacted_url(&self, path: &str, secret: &Secret) -> String {
+        let url = match self {
+            Transport::Http { base_url, .. } => format!("{}{}", base_url, path),
+            Transport::Unix { socket } => return secret.redact(&format!("unix://{}{}", socket.display(), path)),
+        };
+        let Ok(mut parsed) = reqwest::Url::parse(&url) else {
+            return secret.redact(&url);
+        };
+
+        // Brackets would be percent-encoded inside a URL
+        if parsed.password().is_some() {
+            parsed.set_password(Some("redacted")).ok();
+        }
+        let sensitive = |key: &str| key == "token" || key == "secret";
+        if parsed.query_pairs().any(|(key, _)| sensitive(&key)) {
+            let query: Vec<(String, String)> = parsed
+                .query_pairs()
+                .map(|(key, value)| {
+                    let value = if sensitive(&key) { "redacted".into() } else { value };
+                    (key.into_owned(), value.into_owned())
+                })
+                .collect();
+            parsed.query_pairs_mut().clear().extend_pairs(query);
+        }
+        secret.redact(parsed.as_str())
+    }
+
+    async fn send_request(
+        &self,
+        method: Method,
+        path: &str,
+        secret: &str,
+        body: Option<&serde_json::Value>,
+        timeout: Option<Duration>,
+    ) -> Result<ApiResponse> {
+        match self {
+            Transport::Http { client, stream_client, base_url } => {
+                let client = if timeout.is_some() { client } else { stream_client };
+                let mut request = client.request(method, format!("{}{}", base_url, path));
+
+                if let Some(timeout) = timeout {
+                    request = request.timeout(timeout);
+                }
+                if !secret.is_empty() {
+                    let mut authorization = HeaderValue::from_str(&format!("Bearer {}", secret))
+                        .context("Secret contains characters not allowed in a header")?;
+                    authorization.set_sensitive(true);
+                    request = request.header(AUTHORIZATION, authorization);
+                }
+                if let Some(body) = body {
+                    request = request.json(body);
+                }
+
+                let response = request.send().await?;
+                Ok(ApiResponse {
+                    status: response.status(),
+                    body: ResponseBody::Http(response),
+                })
+            }
+            Transport::Unix { socket } => {
+                // Like reqwest's timeout, the deadline lasts until the body has been read
+                let deadline = timeout.map(|timeout| tokio::time::Instant::now() + timeout);
+                let send = Self::send_unix(socket, method, path, secret, body, deadline);
+                with_deadline(deadline, send).await
+            }
+        }
+    }
+
+    async fn send_unix(
+        socket: &Path,
+        method: Method,
+        path: &str,
+        secret: &str,
+        body: Option<&serde_json::Value>,
+        deadline: Option<tokio::time::Instant>,
+    ) -> Result<ApiResponse> {
+        let stream = UnixStream::connect(socket)
+            .await
+            .with_context(|| format!("Failed to connect to {}", socket.display()))?;
+
+        let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+            .await
+            .context("HTTP handshake over Unix socket failed")?;
+
+        // Drive the connection until the response body has been read
+        tokio::spawn(async move {
+            connection.await.ok();
+        });
+
+        let mut request = hyper::Request::builder()
+            .method(method)
+            .uri(path)
+            .header("Host", "localhost");
+
+        if !secret.is_empty() {
+            request = request.header("Authorization", format!("Bearer {}", secret));
+        }
+
+        let payload = match body {
+            Some(body) => {
+                request = request.header("Content-Type", "application/json");
+                Bytes::from(serde_json::to_vec(body).context("Failed to serialize request body")?)
+            }
+            None => Bytes::new(),
+        };
+
+        let request = request
+            .body(Full::new(payload))
+            .context("Failed to build request")?;
+
+        let response = sender
+            .send_request(request)
+            .await
+            .context("Request over Unix socket failed")?;
+
+        Ok(ApiResponse {
+            status: response.status(),
+            body: ResponseBody::Unix(response.into_body(), deadline),
+        })
+    }
+}
+
+impl ApiResponse {
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// The next chunk of a streaming body, or `None` at the end.
+    pub async fn chunk(&mut self) -> Result<Option<Bytes>> {
+        match self.body {
+            ResponseBody::Http(ref mut response) => Ok(response.chunk().await?),
+            ResponseBody::Unix(ref mut body, deadline) => loop {
+                match with_deadline(deadline, async { Ok(body.frame().await) }).await? {
+                    Some(frame) => {
+                        if let Ok(data) = frame?.into_data() {
+                            return Ok(Some(data));
+                        }
+                    }
+                    None => return Ok(None),
+                }
+            },
+        }
+    }
+
+    pub async fn text(self) -> Result<String> {
+        match self.body {
+            ResponseBody::Http(response) => Ok(response.text().await?),
+            ResponseBody::Unix(body, deadline) => {
+                let bytes = with_deadline(deadline, async { Ok(body.collect().await?) }).await?.to_bytes();
+                Ok(String::from_utf8_lossy(&bytes).into_owned())
+            }
+        }
+    }
+
+    pub async fn json<T: DeserializeOwned>(self) -> Result<T> {
+        match self.body {
+            ResponseBody::Http(response) => Ok(response.json().await?),
+            ResponseBody::Unix(body, deadline) => {
+                let bytes = with_deadline(deadline, async { Ok(body.collect().await?) }).await?.to_bytes();
+                Ok(serde_json::from_slice(&bytes)?)
+            }
+        }
+    }
+}
+
+/// Runs a Unix socket request step, failing once `deadline` has passed.
+async fn with_deadline<T>(
+    deadline: Option<tokio::time::Instant>,
+    step: impl std::future::Future<Output = Result<T>>,
+) -> Result<T> {
+    match deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline, step)
+            .await
+            .context("Request over Unix socket timed out")?,
+        None => step.await,
+    }
+}
@@ -1,15 +1,85 @@
 use anyhow::{Context, Result};
 use std::collections::HashMap;
 use indexmap::IndexMap;
-use tray_icon::menu::{Menu, MenuEvent, MenuItem, MenuId, PredefinedMenuItem, Submenu};
-use tray_icon::{TrayIcon, TrayIconBuilder};
+use std::time::Instant;
+use tracing::warn;
+#[cfg(not(feature = "sni"))]
+use tray_icon::menu::{CheckMenuItem, IconMenuItem, Menu, MenuEvent, MenuItem, MenuId, PredefinedMenuItem, Submenu};
+#[cfg(not(feature = "sni"))]
+use tray_icon::{Icon, MouseButton, MouseButtonState, TrayIcon, TrayIconBuilder, TrayIconEvent};
 
-use crate::models::{ProxyGroup, TrayEvent};
+#[cfg(feature = "sni")]
+use crate::sni::menu::{CheckMenuItem, IconMenuItem, Menu, MenuEvent, MenuItem, MenuId, PredefinedMenuItem, Submenu};
+#[cfg(feature = "sni")]
+use crate::sni::{Icon, TrayIcon, TrayIconBuilder, TrayIconEvent};
+
+use crate::config::{Config, IconActionsConfig};
+use crate::icons::IconCache;
+use crate::logs::LOG_LEVELS;
+use crate::models::{
+    CoreConfig, CoreConfigPatch, CoreStatus, GroupType, LogEntry, NodeInfo, ProxyGroup, TrayEvent,
+    TunConfig,
+};
+
+const MAX_LOG_LINE_CHARS: usize = 80;
+
+/// Placeholders understood in `tooltip_template`.
+pub const TOOLTIP_PLACEHOLDERS: [&str; 9] = [
+    "controller", "state", "mode", "version", "memory", "connected_for", "groups", "last_refresh", "error",
+];
 
 pub struct TrayManager {
     tray_icon: TrayIcon,
     menu: Menu,
     menu_id_map: HashMap<MenuId, String>,
+    core_menu: Option<CoreMenu>,
+    core_status: Option<CoreStatus>,
+    logs_submenu: Option<Submenu>,
+    recent_logs: Vec<LogEntry>,
+    settings_menu: Option<SettingsMenu>,
+    core_config: Option<CoreConfig>,
+    controllers: Vec<(String, bool)>,
+    active_controller: usize,
+    icon_actions: IconActionsConfig,
+    tooltip: TooltipState,
+}
+
+/// What the tooltip shows besides the core status, kept between renders.
+#[derive(Default)]
+struct TooltipState {
+    template: String,
+    /// "group: node 🟢 85ms" for each shown group
+    groups: Vec<String>,
+    last_refresh: Option<Instant>,
+    last_error: Option<String>,
+}
+
+/// Input on the tray icon itself, bound to an action by `[icon_actions]`.
+enum IconInput {
+    LeftClick,
+    MiddleClick,
+    // tray-icon has no scroll events
+    #[cfg(feature = "sni")]
+    ScrollUp,
+    #[cfg(feature = "sni")]
+    ScrollDown,
+}
+
+/// Items of the "Core settings" submenu; the log levels behave as radio items.
+struct SettingsMenu {
+    tun: CheckMenuItem,
+    allow_lan: CheckMenuItem,
+    ipv6: CheckMenuItem,
+    log_levels: Vec<(String, CheckMenuItem)>,
+}
+
+/// Read-only items of the "Core" submenu, updated in place as stats arrive.
+struct CoreMenu {
+    version: MenuItem,
+    memory: MenuItem,
+    connected_for: MenuItem,
+    restart: MenuItem,
+    update_geo: MenuItem,
 }
 
 impl TrayManager {
@@ -29,49 +99,117 @@ impl TrayManager {
             tray_icon,
             menu,
             menu_id_map: HashMap::new(),
+            core_menu: None,
+            core_status: None,
+            logs_submenu: None,
+            recent_logs: Vec::new(),
+            settings_menu: None,
+            core_config: None,
+            controllers: Vec::new(),
+            active_controller: 0,
+            icon_actions: IconActionsConfig::default(),
+            tooltip: TooltipState::default(),
         })
     }
 
-    pub fn update_menu(&mut self, proxy_groups: &IndexMap<String, ProxyGroup>, node_delays: &IndexMap<String, u32>, green_threshold: u32, yellow_threshold: u32) -> Result<()> {
+    pub fn update_menu(&mut self, proxy_groups: &IndexMap<String, ProxyGroup>, nodes: &IndexMap<String, NodeInfo>, config: &Config, supports_pinning: bool, icons: &IconCache) -> Result<()> {
         // Clear existing menu ID map
         self.menu_id_map.clear();
+        self.icon_actions = config.icon_actions.clone();
+        self.tooltip.template = config.tooltip_template.clone();
+        self.tooltip.groups = proxy_groups
+            .iter()
+            .map(|(group_name, group)| {
+                let Some(ref now) = group.now else {
+                    return format!("{}: -", group_name);
+                };
+                match nodes.get(now).and_then(|node| node.delay) {
+                    Some(delay) => format!("{}: {} {} {}ms", group_name, now, delay_indicator(delay, config), delay),
+                    None => format!("{}: {}", group_name, now),
+                }
+            })
+            .collect();
         let new_menu = Menu::new();
 
+        // Add a controller switcher when more than one controller is configured
+        if self.controllers.len() > 1 {
+            let (active_name, _) = &self.controllers[self.active_controller];
+            let controller_submenu = Submenu::new(format!("Controller: {}", active_name), true);
+            for (index, (name, connected)) in self.controllers.iter().enumerate() {
+                let state = if *connected { "🟢" } else { "🔴" };
+                let item = CheckMenuItem::new(format!("{} {}", state, name), true, index == self.active_controller, None);
+                self.menu_id_map.insert(item.id().clone(), format!("controller::{}", name));
+                controller_submenu.append(&item).ok();
+            }
+            new_menu.append(&controller_submenu).ok();
+            new_menu.append(&PredefinedMenuItem::separator()).ok();
+        }
+
         // Add proxy groups as submenus
         for (group_name, group) in proxy_groups {
-            if let Some(ref nodes) = group.all {
-                // Create submenu for this group
-                let submenu = Submenu::new(group_name, true);
+            if let Some(ref members) = group.all {
+                // Create submenu for this group, labelled with its type
+                let header = match (&group.group_type, &group.strategy) {
+                    (GroupType::LoadBalance, Some(strategy)) => {
+                        format!("{} ({}: {})", group_name, group.group_type, strategy)
+                    }
+                    _ => format!("{} ({})", group_name, group.group_type),
+                };
+                let submenu = Submenu::new(header, true);
+
+                // Submenus cannot carry icons, so show the group icon as a header item
+                if let Some(icon) = group.icon.as_deref().and_then(|url| icons.get(url)) {
+                    let icon_item = IconMenuItem::new(group_name, false, Some(icon), None);
+                    submenu.append(&icon_item).ok();
+                    submenu.append(&PredefinedMenuItem::separator()).ok();
+                }
+
+                // Only Selector groups are freely switchable; auto-managed groups
+                // can be pinned when the core supports it, everything else is read-only
+                let selectable = match group.group_type {
+                    GroupType::Selector => true,
+                    _ if group.is_auto() => supports_pinning,
+                    _ => false,
+                };
+
+                if group.is_auto() && supports_pinning {
+                    let unpin_item = MenuItem::new("Unpin (auto)", group.fixed.is_some(), None);
+                    self.menu_id_map.insert(unpin_item.id().clone(), format!("unpin::{}", group_name));
+                    submenu.append(&unpin_item).ok();
+                    submenu.append(&PredefinedMenuItem::separator()).ok();
+                }
 
                 // Add all nodes to the submenu
-                for node_name in nodes {
+                for (index, node_name) in members.iter().enumerate() {
                     let is_current = group.now.as_ref() == Some(node_name);
+                    let marker = if group.group_type == GroupType::Relay {
+                        // Relay members are hops of a chain, shown in order
+                        format!("{}. ", index + 1)
+                    } else if group.fixed.as_ref() == Some(node_name) {
+                        "📌 ".to_string()
+                    } else if is_current {
+                        "✓ ".to_string()
+                    } else {
+                        String::new()
+                    };
+
+                    let node = nodes.get(node_name);
 
                     // Build menu text with delay and color indicator if available
-                    let menu_text = if let Some(&delay) = node_delays.get(node_name) {
-                        // Determine color indicator based on delay
-                        let color_indicator = if delay < green_threshold {
-                            "🟢" // Green
-                        } else if delay < yellow_threshold {
-                            "🟡" // Yellow
-                        } else {
-                            "🔴" // Red
-                        };
-
-                        if is_current {
-                            format!("✓ {} {} {}ms", node_name, color_indicator, delay)
-                        } else {
-                            format!("{} {} {}ms", node_name, color_indicator, delay)
-                        }
+                    let mut menu_text = if let Some(delay) = node.and_then(|node| node.delay) {
+                        format!("{}{} {} {}ms", marker, node_name, delay_indicator(delay, config), delay)
                     } else {
-                        if is_current {
-                            format!("✓ {}", node_name)
-                        } else {
-                            node_name.clone()
-                        }
+                        format!("{}{}", marker, node_name)
                     };
 
-                    let menu_item = MenuItem::new(menu_text, true, None);
+                    // Append protocol and capability badges, e.g. "[vless·UDP]"
+                    if config.show_node_badges {
+                        if let Some(node) = node {
+                            menu_text = format!("{} {}", menu_text, node.badge());
+                        }
+                    }
+
+                    let menu_item = MenuItem::new(menu_text, selectable, None);
 
                     // Store menu ID mapping for event handling
                     let item_key = format!("node::{}::{}", group_name, node_name);
@@ -88,6 +226,89 @@ impl TrayManager {
         // Add separator
         new_menu.append(&PredefinedMenuItem::separator()).ok();
 
+        // Add named selection presets
+        let presets_submenu = Submenu::new("Presets", true);
+        for preset in &config.presets {
+            let preset_item = MenuItem::new(&preset.name, true, None);
+            self.menu_id_map.insert(preset_item.id().clone(), format!("preset::{}", preset.name));
+            presets_submenu.append(&preset_item).ok();
+        }
+        if !config.presets.is_empty() {
+            presets_submenu.append(&PredefinedMenuItem::separator()).ok();
+        }
+        let save_preset_item = MenuItem::new("Save current selections", true, None);
+        self.menu_id_map.insert(save_preset_item.id().clone(), "preset-save".to_string());
+        presets_submenu.append(&save_preset_item).ok();
+        new_menu.append(&presets_submenu).ok();
+
+        // Add read-only core status submenu
+        let core_submenu = Submenu::new("Core", true);
+        let core_menu = CoreMenu {
+            version: MenuItem::new("Version: unknown", false, None),
+            memory: MenuItem::new("Memory: n/a", false, None),
+            connected_for: MenuItem::new("Connected for: n/a", false, None),
+            restart: MenuItem::new("Restart core", false, None),
+            update_geo: MenuItem::new("Update GeoIP/GeoSite", false, None),
+        };
+        let reload_item = MenuItem::new("Reload config", true, None);
+        self.menu_id_map.insert(reload_item.id().clone(), "reload".to_string());
+        self.menu_id_map.insert(core_menu.restart.id().clone(), "restart".to_string());
+        self.menu_id_map.insert(core_menu.update_geo.id().clone(), "update-geo".to_string());
+        core_submenu.append(&core_menu.version).ok();
+        core_submenu.append(&core_menu.memory).ok();
+        core_submenu.append(&core_menu.connected_for).ok();
+        core_submenu.append(&PredefinedMenuItem::separator()).ok();
+        core_submenu.append(&reload_item).ok();
+        core_submenu.append(&core_menu.restart).ok();
+        core_submenu.append(&core_menu.update_geo).ok();
+        new_menu.append(&core_submenu).ok();
+        self.core_menu = Some(core_menu);
+        if let Some(status) = self.core_status.clone() {
+            self.update_core_status(&status)?;
+        }
+
+        // Add runtime core settings toggles
+        let settings_submenu = Submenu::new("Core settings", true);
+        let settings_menu = SettingsMenu {
+            tun: CheckMenuItem::new("TUN", true, false, None),
+            allow_lan: CheckMenuItem::new("Allow LAN", true, false, None),
+            ipv6: CheckMenuItem::new("IPv6", true, false, None),
+            log_levels: LOG_LEVELS
+                .iter()
+                .map(|level| (level.to_string(), CheckMenuItem::new(level, true, false, None)))
+                .collect(),
+        };
+        self.menu_id_map.insert(settings_menu.tun.id().clone(), "config::tun".to_string());
+        self.menu_id_map.insert(settings_menu.allow_lan.id().clone(), "config::allow-lan".to_string());
+        self.menu_id_map.insert(settings_menu.ipv6.id().clone(), "config::ipv6".to_string());
+        settings_submenu.append(&settings_menu.tun).ok();
+        settings_submenu.append(&settings_menu.allow_lan).ok();
+        settings_submenu.append(&settings_menu.ipv6).ok();
+        let log_level_submenu = Submenu::new("Log level", true);
+        for (level, item) in &settings_menu.log_levels {
+            self.menu_id_map.insert(item.id().clone(), format!("config::log-level::{}", level));
+            log_level_submenu.append(item).ok();
+        }
+        settings_submenu.append(&log_level_submenu).ok();
+        new_menu.append(&settings_submenu).ok();
+        self.settings_menu = Some(settings_menu);
+        self.sync_settings_menu();
+
+        // Add recent warnings and errors from the core log
+        let logs_submenu = Submenu::new("Logs", true);
+        new_menu.append(&logs_submenu).ok();
+        self.logs_submenu = Some(logs_submenu);
+        let recent_logs = std::mem::take(&mut self.recent_logs);
+        self.update_logs(recent_logs);
+
+        // Add settings window
+        #[cfg(feature = "gtk-tray")]
+        {
+            let settings_item = MenuItem::new("Settings…", true, None);
+            self.menu_id_map.insert(settings_item.id().clone(), "settings".to_string());
+            new_menu.append(&settings_item).ok();
+        }
+
         // Add refresh option
         let refresh_item = MenuItem::new("Refresh", true, None);
         self.menu_id_map.insert(refresh_item.id().clone(), "refresh".to_string());
@@ -105,6 +326,150 @@ impl TrayManager {
         Ok(())
     }
 
+    /// Refreshes the "Core" submenu and tooltip without rebuilding the menu.
+    pub fn update_core_status(&mut self, status: &CoreStatus) -> Result<()> {
+        let version = match status.version {
+            Some(ref version) => format!("{} {}", version.flavour.name(), version.version),
+            None => "unknown".to_string(),
+        };
+        let memory = match status.memory {
+            Some(ref memory) => format_bytes(memory.inuse),
+            None => "n/a".to_string(),
+        };
+        let connected_for = match status.connected_since {
+            Some(connected_since) => format_duration(connected_since.elapsed()),
+            None => "down".to_string(),
+        };
+
+        if let Some(ref core_menu) = self.core_menu {
+            core_menu.version.set_text(format!("Version: {}", version));
+            core_menu.memory.set_text(format!("Memory: {}", memory));
+            core_menu.connected_for.set_text(format!("Connected for: {}", connected_for));
+
+            // Restart and geo updates are only offered by cores that support them
+            let flavour = status.version.as_ref().map(|version| version.flavour);
+            core_menu.restart.set_enabled(flavour.is_some_and(|flavour| flavour.supports_restart()));
+            core_menu.update_geo.set_enabled(flavour.is_some_and(|flavour| flavour.supports_geo_update()));
+        }
+
+        self.core_status = Some(status.clone());
+        self.update_tooltip()
+    }
+
+    /// Records the outcome of the latest refresh of the shown controller.
+    pub fn set_refresh_state(&mut self, last_refresh: Option<Instant>, last_error: Option<String>) {
+        self.tooltip.last_refresh = last_refresh;
+        self.tooltip.last_error = last_error;
+    }
+
+    /// Renders `tooltip_template`; lines left empty by the placeholders are dropped.
+    fn update_tooltip(&self) -> Result<()> {
+        let status = self.core_status.as_ref();
+        let version = match status.and_then(|status| status.version.as_ref()) {
+            Some(version) => format!("{} {}", version.flavour.name(), version.version),
+            None => "unknown".to_string(),
+        };
+        let memory = match status.and_then(|status| status.memory.as_ref()) {
+            Some(memory) => format_bytes(memory.inuse),
+            None => "n/a".to_string(),
+        };
+        let connected_for = match status.and_then(|status| status.connected_since) {
+            Some(connected_since) => format_duration(connected_since.elapsed()),
+            None => "down".to_string(),
+        };
+        let (controller, connected) = self
+            .controllers
+            .get(self.active_controller)
+            .map(|(name, connected)| (name.as_str(), *connected))
+            .unwrap_or(("", status.is_some_and(|status| status.connected_since.is_some())));
+        let mode = match self.core_config {
+            Some(ref core_config) if !core_config.mode.is_empty() => capitalize(&core_config.mode),
+            _ => "unknown".to_string(),
+        };
+        let last_refresh = match self.tooltip.last_refresh {
+            Some(at) if at.elapsed().as_secs() < 60 => format!("{}s ago", at.elapsed().as_secs()),
+            Some(at) => format!("{} ago", format_duration(at.elapsed())),
+            None => "never".to_string(),
+        };
+        let error = match self.tooltip.last_error {
+            Some(ref error) => format!("Error: {}", error),
+            None => String::new(),
+        };
+
+        let values = [
+            ("controller", controller.to_string()),
+            ("state", if connected { "connected" } else { "disconnected" }.to_string()),
+            ("mode", mode),
+            ("version", version),
+            ("memory", memory),
+            ("connected_for", connected_for),
+            ("groups", self.tooltip.groups.join("\n")),
+            ("last_refresh", last_refresh),
+            ("error", error),
+        ];
+        let mut tooltip = self.tooltip.template.clone();
+        for (placeholder, value) in values {
+            tooltip = tooltip.replace(&format!("{{{}}}", placeholder), &value);
+        }
+        let tooltip: Vec<&str> = tooltip.lines().filter(|line| !line.trim().is_empty()).collect();
+
+        self.tray_icon
+            .set_tooltip(Some(tooltip.join("\n")))
+            .context("Failed to set tooltip")?;
+        Ok(())
+    }
+
+    /// Sets the controller names with their connection state, and which one the menu shows.
+    pub fn set_controllers(&mut self, controllers: Vec<(String, bool)>, active: usize) {
+        self.controllers = controllers;
+        self.active_controller = active;
+    }
+
+    pub fn update_core_config(&mut self, core_config: CoreConfig) {
+        self.core_config = Some(core_config);
+        self.sync_settings_menu();
+        if let Err(e) = self.update_tooltip() {
+            warn!("{}", e);
+        }
+    }
+
+    /// Makes the check items reflect the last known core config. Clicking a
+    /// check item toggles it locally, so this also undoes optimistic toggles.
+    fn sync_settings_menu(&self) {
+        let (Some(settings_menu), Some(core_config)) = (&self.settings_menu, &self.core_config) else {
+            return;
+        };
+
+        let tun_enabled = core_config.tun.as_ref().is_some_and(|tun| tun.enable);
+        settings_menu.tun.set_enabled(core_config.tun.is_some());
+        settings_menu.tun.set_checked(tun_enabled);
+        settings_menu.allow_lan.set_checked(core_config.allow_lan);
+        settings_menu.ipv6.set_checked(core_config.ipv6);
+        for (level, item) in &settings_menu.log_levels {
+            item.set_checked(*level == core_config.log_level);
+        }
+    }
+
+    /// Replaces the entries of the "Logs" submenu.
+    pub fn update_logs(&mut self, entries: Vec<LogEntry>) {
+        if let Some(ref logs_submenu) = self.logs_submenu {
+            while logs_submenu.remove_at(0).is_some() {}
+
+            if entries.is_empty() {
+                logs_submenu.append(&MenuItem::new("No recent warnings", false, None)).ok();
+            }
+            for entry in &entries {
+                let mut text = format!("[{}] {}", entry.level, entry.payload);
+                if text.chars().count() > MAX_LOG_LINE_CHARS {
+                    text = text.chars().take(MAX_LOG_LINE_CHARS).collect::<String>() + "…";
+                }
+                logs_submenu.append(&MenuItem::new(text, false, None)).ok();
+            }
+        }
+
+        self.recent_logs = entries;
+    }
+
     pub fn set_icon_connected(&mut self) -> Result<()> {
         self.tray_icon.set_icon(Some(Self::create_connected_icon()?))?;
         Ok(())
@@ -119,10 +484,37 @@ impl TrayManager {
         // Check for menu events
         if let Ok(menu_event) = MenuEvent::receiver().try_recv() {
             if let Some(action_key) = self.menu_id_map.get(&menu_event.id) {
+                #[cfg(feature = "gtk-tray")]
+                if action_key == "settings" {
+                    return Some(TrayEvent::OpenSettings);
+                }
+
                 if action_key == "refresh" {
                     return Some(TrayEvent::Refresh);
                 } else if action_key == "quit" {
                     return Some(TrayEvent::Quit);
+                } else if let Some(name) = action_key.strip_prefix("controller::") {
+                    return Some(TrayEvent::SelectController {
+                        name: name.to_string(),
+                    });
+                } else if action_key == "preset-save" {
+                    return Some(TrayEvent::SavePreset);
+                } else if let Some(name) = action_key.strip_prefix("preset::") {
+                    return Some(TrayEvent::ApplyPreset {
+                        name: name.to_string(),
+                    });
+                } else if action_key == "reload" {
+                    return Some(TrayEvent::ReloadConfig);
+                } else if action_key == "restart" {
+                    return Some(TrayEvent::RestartCore);
+                } else if action_key == "update-geo" {
+                    return Some(TrayEvent::UpdateGeo);
+                } else if let Some(setting) = action_key.strip_prefix("config::") {
+                    return self.config_patch(setting).map(TrayEvent::PatchCoreConfig);
+                } else if let Some(group) = action_key.strip_prefix("unpin::") {
+                    return Some(TrayEvent::UnpinGroup {
+                        group: group.to_string(),
+                    });
                 } else if action_key.starts_with("node::") {
                     // Parse: "node::group_name::node_name"
                     let parts: Vec<&str> = action_key.split("::").collect();
@@ -136,28 +528,171 @@ impl TrayManager {
             }
         }
 
+        // Clicks and scrolling on the icon itself
+        if let Some(input) = Self::poll_icon_input() {
+            let action = match input {
+                IconInput::LeftClick => &self.icon_actions.left_click,
+                IconInput::MiddleClick => &self.icon_actions.middle_click,
+                #[cfg(feature = "sni")]
+                IconInput::ScrollUp => &self.icon_actions.scroll_up,
+                #[cfg(feature = "sni")]
+                IconInput::ScrollDown => &self.icon_actions.scroll_down,
+            };
+            return self.icon_action(action);
+        }
+
         None
     }
 
-    fn create_default_icon() -> Result<tray_icon::Icon> {
+    /// libappindicator does not report clicks on Linux, so this mostly
+    /// matters on other platforms.
+    #[cfg(not(feature = "sni"))]
+    fn poll_icon_input() -> Option<IconInput> {
+        match TrayIconEvent::receiver().try_recv().ok()? {
+            TrayIconEvent::Click {
+                button,
+                button_state: MouseButtonState::Up,
+                ..
+            } => match button {
+                MouseButton::Left => Some(IconInput::LeftClick),
+                MouseButton::Middle => Some(IconInput::MiddleClick),
+                MouseButton::Right => None,
+            },
+            _ => None,
+        }
+    }
+
+    #[cfg(feature = "sni")]
+    fn poll_icon_input() -> Option<IconInput> {
+        match TrayIconEvent::receiver().try_recv().ok()? {
+            TrayIconEvent::Activate => Some(IconInput::LeftClick),
+            TrayIconEvent::SecondaryActivate => Some(IconInput::MiddleClick),
+            // Hosts report scrolling up as a positive delta
+            TrayIconEvent::Scroll { delta, horizontal: false } if delta > 0 => Some(IconInput::ScrollUp),
+            TrayIconEvent::Scroll { delta, horizontal: false } if delta < 0 => Some(IconInput::ScrollDown),
+            TrayIconEvent::Scroll { .. } => None,
+        }
+    }
+
+    /// The menu event for an `[icon_actions]` action, named like the `[hotkeys.binds]` actions.
+    fn icon_action(&self, action: &str) -> Option<TrayEvent> {
+        match action {
+            "" => None,
+            "refresh" => Some(TrayEvent::Refresh),
+            "next" => Some(TrayEvent::CycleNode { step: 1 }),
+            "prev" => Some(TrayEvent::CycleNode { step: -1 }),
+            "fastest" => Some(TrayEvent::FastestNode),
+            "pick" => Some(TrayEvent::PickNode),
+            "toggle-mode" => {
+                let current = &self.core_config.as_ref()?.mode;
+                let mode = if current.eq_ignore_ascii_case("global") { "rule" } else { "global" };
+                Some(TrayEvent::PatchCoreConfig(CoreConfigPatch {
+                    mode: Some(mode.to_string()),
+                    ..CoreConfigPatch::default()
+                }))
+            }
+            _ => {
+                if let Some(mode) = action.strip_prefix("mode:") {
+                    Some(TrayEvent::PatchCoreConfig(CoreConfigPatch {
+                        mode: Some(mode.to_string()),
+                        ..CoreConfigPatch::default()
+                    }))
+                } else {
+                    action.strip_prefix("preset:").map(|name| TrayEvent::ApplyPreset {
+                        name: name.to_string(),
+                    })
+                }
+            }
+        }
+    }
+
+    /// Builds the patch for a clicked "Core settings" item from the last known config.
+    fn config_patch(&self, setting: &str) -> Option<CoreConfigPatch> {
+        let core_config = self.core_config.as_ref()?;
+        let mut patch = CoreConfigPatch::default();
+
+        match setting {
+            "tun" => {
+                let enabled = core_config.tun.as_ref().is_some_and(|tun| tun.enable);
+                patch.tun = Some(TunConfig { enable: !enabled });
+            }
+            "allow-lan" => patch.allow_lan = Some(!core_config.allow_lan),
+            "ipv6" => patch.ipv6 = Some(!core_config.ipv6),
+            _ => {
+                let level = setting.strip_prefix("log-level::")?;
+                if level == core_config.log_level {
+                    // Re-selecting the active level only needs the check restored
+                    self.sync_settings_menu();
+                    return None;
+                }
+                patch.log_level = Some(level.to_string());
+            }
+        }
+
+        Some(patch)
+    }
+
+    fn create_default_icon() -> Result<Icon> {
         Self::load_icon_from_bytes(include_bytes!("../assets/icon.png"))
     }
 
-    fn create_connected_icon() -> Result<tray_icon::Icon> {
+    fn create_connected_icon() -> Result<Icon> {
         Self::load_icon_from_bytes(include_bytes!("../assets/icon.png"))
     }
 
-    fn create_disconnected_icon() -> Result<tray_icon::Icon> {
+    fn create_disconnected_icon() -> Result<Icon> {
         Self::load_icon_from_bytes(include_bytes!("../assets/icon.png"))
     }
 
-    fn load_icon_from_bytes(bytes: &[u8]) -> Result<tray_icon::Icon> {
+    fn load_icon_from_bytes(bytes: &[u8]) -> Result<Icon> {
         let img = image::load_from_memory(bytes)
             .context("Failed to load icon from bytes")?
             .to_rgba8();
         let (width, height) = img.dimensions();
         let rgba = img.into_raw();
-        tray_icon::Icon::from_rgba(rgba, width, height)
+        Icon::from_rgba(rgba, width, height)
             .context("Failed to create icon from RGBA data")
     }
 }
+
+/// The colour shown next to a delay, using the configured thresholds.
+fn delay_indicator(delay: u32, config: &Config) -> &'static str {
+    if delay < config.delay_green_threshold {
+        "🟢"
+    } else if delay < config.delay_yellow_threshold {
+        "🟡"
+    } else {
+        "🔴"
+    }
+}
+
+fn capitalize(text: &str) -> String {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+fn format_duration(duration: std::time::Duration) -> String {
+    let secs = duration.as_secs();
+    let (days, hours, minutes) = (secs / 86400, secs % 86400 / 3600, secs % 3600 / 60);
+    if days > 0 {
+        format!("{}d {}h", days, hours)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    } else {
+        format!("{}m", minutes)
+    }
+}
@@ -0,0 +1,556 @@
+use indexmap::IndexMap;
+use regex::Regex;
+use std::collections::HashSet;
+use std::fmt;
+use toml_edit::{Document, Item};
+
+#[cfg(not(feature = "sni"))]
+use crate::config::IconActionsConfig;
+use crate::config::{Config, TlsConfig};
+use crate::hotkeys;
+use crate::layers::Layered;
+use crate::logging;
+use crate::logs::LOG_LEVELS;
+use crate::models::ProxyGroup;
+use crate::tls;
+use crate::tray::TOOLTIP_PLACEHOLDERS;
+
+const KNOWN_GROUP_TYPES: [&str; 6] = ["Selector", "URLTest", "Fallback", "LoadBalance", "Relay", "Smart"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem found in the config. `source` is the layer the offending value
+/// came from (a config file, `$CLBAR_*` variable or `--set`) and is missing for
+/// defaults; `line` and `column` are 1-based and only set for config files.
+#[derive(Debug, Clone)]
+pub struct Problem {
+    pub severity: Severity,
+    pub key: String,
+    pub source: Option<String>,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+impl Problem {
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+
+        match (&self.source, self.line, self.column) {
+            (Some(source), Some(line), Some(column)) => write!(f, "{}:{}:{}: ", source, line, column)?,
+            (Some(source), _, _) => write!(f, "{}: ", source)?,
+            _ => {}
+        }
+        write!(f, "{}: {}: {}", severity, self.key, self.message)
+    }
+}
+
+/// Collects problems and resolves dotted keys like `controllers.1.url` to the
+/// layer that set them and, for config files, the position in that file.
+pub struct Validator<'a> {
+    sources: Option<&'a IndexMap<String, String>>,
+    files: Vec<(&'a str, &'a str, Document<&'a str>)>,
+    problems: Vec<Problem>,
+}
+
+impl<'a> Validator<'a> {
+    /// `layered` is None for a config that was not loaded from the layers,
+    /// such as one edited in the settings window.
+    pub fn new(layered: Option<&'a Layered>) -> Self {
+        let files = layered
+            .map(|layered| {
+                layered
+                    .files
+                    .iter()
+                    .filter_map(|(source, content)| {
+                        Some((source.as_str(), content.as_str(), Document::parse(content.as_str()).ok()?))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Validator {
+            sources: layered.map(|layered| &layered.sources),
+            files,
+            problems: Vec::new(),
+        }
+    }
+
+    pub fn error(&mut self, key: &str, message: impl Into<String>) {
+        self.push(Severity::Error, key, message.into());
+    }
+
+    pub fn warning(&mut self, key: &str, message: impl Into<String>) {
+        self.push(Severity::Warning, key, message.into());
+    }
+
+    pub fn into_problems(self) -> Vec<Problem> {
+        self.problems
+    }
+
+    fn push(&mut self, severity: Severity, key: &str, message: String) {
+        let source = self.source(key);
+        let position = source.and_then(|source| self.position(source, key));
+        self.problems.push(Problem {
+            severity,
+            key: key.to_string(),
+            source: source.map(str::to_string),
+            line: position.map(|(line, _)| line),
+            column: position.map(|(_, column)| column),
+            message,
+        });
+    }
+
+    /// The layer that set `key`. Sources are recorded per value, and arrays
+    /// are values, so `controllers.1.url` is found under `controllers`.
+    fn source(&self, key: &str) -> Option<&'a str> {
+        let sources = self.sources?;
+        let mut prefix = key;
+        loop {
+            if let Some(source) = sources.get(prefix) {
+                return Some(source.as_str());
+            }
+            prefix = &prefix[..prefix.rfind('.')?];
+        }
+    }
+
+    fn position(&self, source: &str, key: &str) -> Option<(usize, usize)> {
+        let (_, content, document) = self.files.iter().find(|(name, _, _)| *name == source)?;
+        let mut item: &Item = document.as_item();
+        for segment in key.split('.') {
+            item = match segment.parse::<usize>() {
+                Ok(index) => item.get(index)?,
+                Err(_) => item.get(segment)?,
+            };
+        }
+
+        let offset = item.span()?.start;
+        let before = &content[..offset];
+        let line = before.matches('\n').count() + 1;
+        let column = before.rsplit('\n').next().unwrap_or_default().chars().count() + 1;
+        Some((line, column))
+    }
+}
+
+/// Checks values that parse fine but make no sense.
+pub fn validate(config: &Config, layered: Option<&Layered>) -> Vec<Problem> {
+    let mut validator = Validator::new(layered);
+
+    if config.controllers.is_empty() {
+        check_url(&mut validator, "clash_api_url", &config.clash_api_url);
+        check_tls(&mut validator, "clash_tls", &config.clash_tls);
+    }
+
+    let mut names = HashSet::new();
+    for (index, controller) in config.controllers.iter().enumerate() {
+        if !names.insert(controller.name.as_str()) {
+            validator.error(
+                &format!("controllers.{}.name", index),
+                format!("duplicate controller name {}", controller.name),
+            );
+        }
+        check_url(&mut validator, &format!("controllers.{}.url", index), &controller.url);
+        check_tls(&mut validator, &format!("controllers.{}.tls", index), &controller.tls);
+    }
+
+    if config.refresh_interval_secs == 0 {
+        validator.error("refresh_interval_secs", "must be at least 1");
+    }
+
+    for (index, group_type) in config.group_types.iter().enumerate() {
+        if !KNOWN_GROUP_TYPES.contains(&group_type.as_str()) {
+            validator.warning(
+                &format!("group_types.{}", index),
+                format!("unknown group type {} (known: {})", group_type, KNOWN_GROUP_TYPES.join(", ")),
+            );
+        }
+    }
+
+    if let Err(e) = reqwest::Url::parse(&config.delay_test_url) {
+        validator.error("delay_test_url", format!("invalid URL {}: {}", config.delay_test_url, e));
+    }
+
+    if config.delay_test_timeout == 0 {
+        validator.error("delay_test_timeout", "must be at least 1 ms");
+    }
+
+    if config.delay_yellow_threshold < config.delay_green_threshold {
+        validator.error(
+            "delay_yellow_threshold",
+            format!(
+                "{} ms is below delay_green_threshold ({} ms)",
+                config.delay_yellow_threshold, config.delay_green_threshold
+            ),
+        );
+    }
+
+    if config.watchdog.interval_secs == 0 {
+        validator.error("watchdog.interval_secs", "must be at least 1");
+    }
+
+    if config.watchdog.max_failures == 0 {
+        validator.warning("watchdog.max_failures", "0 fails over on every check");
+    }
+
+    if config.watchdog.enabled && config.watchdog.groups.is_empty() {
+        validator.warning("watchdog.groups", "the watchdog is enabled but watches no groups");
+    }
+
+    check_pattern(&mut validator, "watchdog.node_pattern", &config.watchdog.node_pattern);
+    check_pattern(&mut validator, "logs.notify_pattern", &config.logs.notify_pattern);
+
+    if !LOG_LEVELS.contains(&config.logs.level.as_str()) {
+        validator.error(
+            "logs.level",
+            format!("unknown level {} (expected one of: {})", config.logs.level, LOG_LEVELS.join(", ")),
+        );
+    }
+
+    if config.logs.buffer_size == 0 {
+        validator.warning("logs.buffer_size", "0 keeps no log lines");
+    }
+
+    if !logging::LEVELS.contains(&config.logging.level.as_str()) {
+        validator.error(
+            "logging.level",
+            format!("unknown level {} (expected one of: {})", config.logging.level, logging::LEVELS.join(", ")),
+        );
+    }
+
+    for action in config.hotkeys.binds.keys() {
+        if hotkeys::bind_command(action).is_none() {
+            validator.warning(
+                &format!("hotkeys.binds.{}", action),
+                "unknown action (expected next, prev, fastest, pick, toggle-mode, mode:<mode> or preset:<name>)",
+            );
+        }
+    }
+
+    let placeholder = Regex::new(r"\{(\w+)\}").expect("valid regex");
+    for captures in placeholder.captures_iter(&config.tooltip_template) {
+        if !TOOLTIP_PLACEHOLDERS.contains(&&captures[1]) {
+            validator.warning(
+                "tooltip_template",
+                format!("unknown placeholder {{{}}} (expected one of {})", &captures[1], TOOLTIP_PLACEHOLDERS.join(", ")),
+            );
+        }
+    }
+
+    let notifications = [
+        ("switch", &config.notifications.switch),
+        ("refresh", &config.notifications.refresh),
+        ("error", &config.notifications.error),
+        ("reconnect", &config.notifications.reconnect),
+        ("failover", &config.notifications.failover),
+        ("action", &config.notifications.action),
+    ];
+    for (kind, settings) in notifications {
+        if !["low", "normal", "critical"].contains(&settings.urgency.as_str()) {
+            validator.warning(
+                &format!("notifications.{}.urgency", kind),
+                format!("unknown urgency {} (expected low, normal or critical)", settings.urgency),
+            );
+        }
+    }
+
+    let icon_actions = [
+        ("left_click", &config.icon_actions.left_click),
+        ("middle_click", &config.icon_actions.middle_click),
+        ("scroll_up", &config.icon_actions.scroll_up),
+        ("scroll_down", &config.icon_actions.scroll_down),
+    ];
+    for (key, action) in icon_actions {
+        if !action.is_empty() && action != "refresh" && hotkeys::bind_command(action).is_none() {
+            validator.warning(
+                &format!("icon_actions.{}", key),
+                format!("unknown action {} (expected a [hotkeys.binds] action, refresh or \"\")", action),
+            );
+        }
+    }
+
+    // libappindicator opens the menu on every click and reports no scrolling
+    #[cfg(not(feature = "sni"))]
+    {
+        let defaults = IconActionsConfig::default();
+        let rebound = [
+            ("left_click", &config.icon_actions.left_click, &defaults.left_click),
+            ("middle_click", &config.icon_actions.middle_click, &defaults.middle_click),
+            ("scroll_up", &config.icon_actions.scroll_up, &defaults.scroll_up),
+            ("scroll_down", &config.icon_actions.scroll_down, &defaults.scroll_down),
+        ];
+        for (key, action, default) in rebound {
+            if action != default {
+                validator.warning(
+                    &format!("icon_actions.{}", key),
+                    "has no effect: this build's tray (libappindicator) does not report clicks or scrolling; build with the sni feature",
+                );
+            }
+        }
+    }
+
+    let mut preset_names = HashSet::new();
+    for (index, preset) in config.presets.iter().enumerate() {
+        if !preset_names.insert(preset.name.as_str()) {
+            validator.warning(
+                &format!("presets.{}.name", index),
+                format!("duplicate preset name {}; only the first is used", preset.name),
+            );
+        }
+    }
+
+    validator.into_problems()
+}
+
+/// Checks that the groups named in the config exist on a live controller.
+pub fn validate_groups(
+    layered: &Layered,
+    controller: &str,
+    proxy_groups: &IndexMap<String, ProxyGroup>,
+) -> Vec<Problem> {
+    let config = &layered.config;
+    let mut validator = Validator::new(Some(layered));
+
+    for (index, group) in config.proxy_groups.iter().enumerate() {
+        if !proxy_groups.contains_key(group) {
+            validator.error(
+                &format!("proxy_groups.{}", index),
+                format!("group {} not found on {} (or its type is not in group_types)", group, controller),
+            );
+        }
+    }
+
+    if config.watchdog.enabled {
+        for (index, group) in config.watchdog.groups.iter().enumerate() {
+            if !proxy_groups.contains_key(group) {
+                validator.warning(
+                    &format!("watchdog.groups.{}", index),
+                    format!("group {} not found on {}", group, controller),
+                );
+            }
+        }
+    }
+
+    if config.delay_test_group != "all" && !proxy_groups.contains_key(&config.delay_test_group) {
+        validator.warning(
+            "delay_test_group",
+            format!("group {} not found on {}", config.delay_test_group, controller),
+        );
+    }
+
+    validator.into_problems()
+}
+
+fn check_url(validator: &mut Validator, key: &str, url: &str) {
+    if let Some(socket) = url.strip_prefix("unix://") {
+        if socket.is_empty() {
+            validator.error(key, "Unix socket URL has no path");
+        }
+        return;
+    }
+
+    match reqwest::Url::parse(url) {
+        Ok(parsed) if parsed.scheme() != "http" && parsed.scheme() != "https" => {
+            validator.error(key, format!("unsupported scheme {} (expected http, https or unix)", parsed.scheme()));
+        }
+        Ok(parsed) if parsed.host_str().is_none() => validator.error(key, format!("{} has no host", url)),
+        Ok(_) => {}
+        Err(e) => validator.error(key, format!("invalid URL {}: {}", url, e)),
+    }
+}
+
+fn check_tls(validator: &mut Validator, key: &str, settings: &TlsConfig) {
+    if !settings.fingerprint.is_empty() {
+        if let Err(e) = tls::parse_fingerprint(&settings.fingerprint) {
+            validator.error(&format!("{}.fingerprint", key), e.to_string());
+        }
+    }
+
+    if !settings.client_key.is_empty() && settings.client_cert.is_empty() {
+        validator.error(&format!("{}.client_key", key), "client_key requires client_cert");
+    }
+
+    if settings.insecure {
+        validator.warning(&format!("{}.insecure", key), "TLS certificate verification is disabled");
+    }
+}
+
+fn check_pattern(validator: &mut Validator, key: &str, pattern: &str) {
+    if pattern.is_empty() {
+        return;
+    }
+
+    if let Err(e) = Regex::new(pattern) {
+        validator.error(key, format!("invalid regex: {}", e));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{test_groups, GroupType};
+
+    const SOURCE: &str = "config.toml";
+
+    /// A config read only from one user file, every value attributed to it.
+    fn user_file(content: &str) -> Layered {
+        let table: toml::Table = toml::from_str(content).expect("valid TOML");
+        Layered {
+            config: toml::from_str(content).expect("valid config"),
+            sources: table.keys().map(|key| (key.clone(), SOURCE.to_string())).collect(),
+            files: vec![(SOURCE.to_string(), content.to_string())],
+        }
+    }
+
+    fn errors(problems: &[Problem]) -> Vec<&Problem> {
+        problems.iter().filter(|problem| problem.is_error()).collect()
+    }
+
+    #[test]
+    fn default_config_has_no_errors() {
+        let problems = validate(&Config::default(), None);
+        assert!(errors(&problems).is_empty(), "{:?}", problems);
+    }
+
+    #[test]
+    fn yellow_threshold_below_green_points_at_the_value() {
+        let layered = user_file("delay_green_threshold = 300\ndelay_yellow_threshold = 200\n");
+        let problems = validate(&layered.config, Some(&layered));
+
+        let errors = errors(&problems);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].key, "delay_yellow_threshold");
+        assert_eq!(errors[0].source.as_deref(), Some(SOURCE));
+        assert_eq!((errors[0].line, errors[0].column), (Some(2), Some(26)));
+        assert_eq!(
+            errors[0].to_string(),
+            "config.toml:2:26: error: delay_yellow_threshold: 200 ms is below delay_green_threshold (300 ms)"
+        );
+    }
+
+    #[test]
+    fn controller_url_problems_point_into_the_array() {
+        let content = r#"
+[[controllers]]
+name = "home"
+url = "http://127.0.0.1:9090"
+
+[[controllers]]
+name = "router"
+url = "ftp://192.168.1.1"
+
+[[controllers]]
+name = "socket"
+url = "unix://"
+"#;
+        let layered = user_file(content);
+        let problems = validate(&layered.config, Some(&layered));
+
+        let errors = errors(&problems);
+        assert_eq!(errors.len(), 2, "{:?}", errors);
+        assert_eq!(errors[0].key, "controllers.1.url");
+        assert_eq!((errors[0].line, errors[0].column), (Some(8), Some(7)));
+        assert!(errors[0].message.contains("unsupported scheme ftp"));
+        assert_eq!(errors[1].key, "controllers.2.url");
+        assert_eq!(errors[1].line, Some(12));
+        assert_eq!(errors[1].message, "Unix socket URL has no path");
+    }
+
+    #[test]
+    fn duplicate_controller_names_are_errors() {
+        let layered = user_file(
+            "[[controllers]]\nname = \"a\"\nurl = \"http://a\"\n\n[[controllers]]\nname = \"a\"\nurl = \"http://b\"\n",
+        );
+        let problems = validate(&layered.config, Some(&layered));
+
+        let errors = errors(&problems);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].key, "controllers.1.name");
+        assert_eq!(errors[0].line, Some(6));
+    }
+
+    #[test]
+    fn values_from_other_layers_name_the_layer_without_a_position() {
+        let mut layered = user_file("refresh_interval_secs = 5\n");
+        layered.config.refresh_interval_secs = 0;
+        layered.sources.insert("refresh_interval_secs".to_string(), "$CLBAR_REFRESH_INTERVAL_SECS".to_string());
+        let problems = validate(&layered.config, Some(&layered));
+
+        let errors = errors(&problems);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, None);
+        assert_eq!(
+            errors[0].to_string(),
+            "$CLBAR_REFRESH_INTERVAL_SECS: error: refresh_interval_secs: must be at least 1"
+        );
+    }
+
+    #[test]
+    fn defaults_have_no_source() {
+        let config = Config {
+            delay_test_timeout: 0,
+            ..Config::default()
+        };
+        let problems = validate(&config, None);
+
+        let errors = errors(&problems);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].source, None);
+        assert_eq!(errors[0].to_string(), "error: delay_test_timeout: must be at least 1 ms");
+    }
+
+    #[cfg(not(feature = "sni"))]
+    #[test]
+    fn rebound_icon_actions_warn_on_gtk_builds() {
+        let layered = user_file("[icon_actions]\nleft_click = \"pick\"\nmiddle_click = \"fastest\"\n");
+        let problems = validate(&layered.config, Some(&layered));
+
+        let keys: Vec<&str> = problems.iter().map(|problem| problem.key.as_str()).collect();
+        assert_eq!(keys, ["icon_actions.middle_click"]);
+        assert_eq!(problems[0].line, Some(3));
+    }
+
+    #[test]
+    fn invalid_patterns_and_levels_are_errors() {
+        let layered = user_file("[watchdog]\nnode_pattern = \"(\"\n\n[logs]\nlevel = \"loud\"\n");
+        let problems = validate(&layered.config, Some(&layered));
+
+        let keys: Vec<&str> = errors(&problems).iter().map(|problem| problem.key.as_str()).collect();
+        assert_eq!(keys, ["watchdog.node_pattern", "logs.level"]);
+    }
+
+    #[test]
+    fn unknown_tooltip_placeholders_are_warnings() {
+        let layered = user_file("tooltip_template = \"{controller} {uptime}\"\n");
+        let problems = validate(&layered.config, Some(&layered));
+
+        assert!(errors(&problems).is_empty());
+        assert!(problems
+            .iter()
+            .any(|problem| problem.key == "tooltip_template" && problem.message.contains("{uptime}")));
+    }
+
+    #[test]
+    fn missing_groups_are_reported_per_controller() {
+        let layered = user_file("proxy_groups = [\"PROXY\", \"Gone\"]\n");
+        let proxy_groups = test_groups(vec![ProxyGroup::test("PROXY", GroupType::Selector, "", &[])]);
+        let problems = validate_groups(&layered, "home", &proxy_groups);
+
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].key, "proxy_groups.1");
+        assert_eq!((problems[0].line, problems[0].column), (Some(1), Some(26)));
+        assert!(problems[0].message.contains("Gone not found on home"));
+    }
+}
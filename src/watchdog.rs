@@ -0,0 +1,289 @@
+use anyhow::{Context, Result};
+use indexmap::IndexMap;
+use regex::Regex;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::task::JoinSet;
+
+use crate::clash_api::ClashApi;
+use crate::config::{Config, WatchdogConfig};
+use crate::models::{GroupType, ProxyGroup, BUILTIN_NODES};
+
+/// A switch the watchdog decided to make for a Selector group.
+#[derive(Debug, Clone)]
+pub struct Failover {
+    pub group: String,
+    pub from: String,
+    pub to: String,
+    pub reason: String,
+}
+
+pub struct Watchdog {
+    config: WatchdogConfig,
+    node_pattern: Option<Regex>,
+    failures: HashMap<String, u32>,
+    last_switch: HashMap<String, Instant>,
+    last_check: Option<Instant>,
+}
+
+impl Watchdog {
+    pub fn new(config: &WatchdogConfig) -> Result<Self> {
+        let node_pattern = if config.node_pattern.is_empty() {
+            None
+        } else {
+            Some(Regex::new(&config.node_pattern).context("Invalid watchdog node_pattern")?)
+        };
+
+        Ok(Watchdog {
+            config: config.clone(),
+            node_pattern,
+            failures: HashMap::new(),
+            last_switch: HashMap::new(),
+            last_check: None,
+        })
+    }
+
+    pub fn is_due(&self) -> bool {
+        if !self.config.enabled {
+            return false;
+        }
+
+        match self.last_check {
+            Some(last) => last.elapsed() >= Duration::from_secs(self.config.interval_secs),
+            None => true,
+        }
+    }
+
+    /// Tests the current node of every watched Selector group and returns the
+    /// switches that should be made. Counters are reset for groups that fail over.
+    ///
+    /// Takes the watchdog by value so the check can run in a spawned task; all
+    /// nodes of a round are tested in parallel.
+    pub async fn check(
+        mut self,
+        clash_api: &ClashApi,
+        config: &Config,
+        proxy_groups: &IndexMap<String, ProxyGroup>,
+    ) -> (Self, Vec<Failover>) {
+        self.last_check = Some(Instant::now());
+
+        // Only Selector groups can be switched manually
+        let watched: Vec<(&String, &String, &Vec<String>)> = self
+            .config
+            .groups
+            .iter()
+            .filter_map(|group_name| {
+                let group = proxy_groups.get(group_name)?;
+                if group.group_type != GroupType::Selector {
+                    return None;
+                }
+                Some((group_name, group.now.as_ref()?, group.all.as_ref()?))
+            })
+            .collect();
+
+        let currents: Vec<&str> = watched.iter().map(|(_, current, _)| current.as_str()).collect();
+        let current_delays = test_delays(clash_api, config, &currents).await;
+
+        let mut failing = Vec::new();
+        for (group_name, current, nodes) in watched {
+            let reason = match current_delays.get(current) {
+                Some(Some(delay)) if *delay <= self.config.max_delay_ms => {
+                    self.failures.remove(group_name);
+                    continue;
+                }
+                Some(Some(delay)) => format!(
+                    "{} delay {}ms exceeds {}ms",
+                    current, delay, self.config.max_delay_ms
+                ),
+                _ => {
+                    let failures = self.failures.entry(group_name.clone()).or_insert(0);
+                    *failures += 1;
+                    if *failures < self.config.max_failures {
+                        continue;
+                    }
+                    format!("{} failed {} consecutive checks", current, failures)
+                }
+            };
+
+            if let Some(last) = self.last_switch.get(group_name) {
+                if last.elapsed() < Duration::from_secs(self.config.cooldown_secs) {
+                    continue;
+                }
+            }
+
+            failing.push((group_name, current, nodes, reason));
+        }
+
+        // Candidates of all failing groups are tested in one parallel round
+        let candidates: Vec<&str> = failing
+            .iter()
+            .flat_map(|(_, current, nodes, _)| nodes.iter().filter(|node| *node != *current && self.is_candidate(node)))
+            .map(String::as_str)
+            .collect();
+        let candidate_delays = test_delays(clash_api, config, &candidates).await;
+
+        let mut failovers = Vec::new();
+        for (group_name, current, nodes, reason) in failing {
+            let Some(target) = self.best_candidate(&candidate_delays, current, nodes) else {
+                continue;
+            };
+
+            self.failures.remove(group_name);
+            self.last_switch.insert(group_name.clone(), Instant::now());
+            failovers.push(Failover {
+                group: group_name.clone(),
+                from: current.clone(),
+                to: target,
+                reason,
+            });
+        }
+
+        (self, failovers)
+    }
+
+    /// Picks the first healthy preferred node, falling back to the fastest
+    /// healthy member. Both must match the pattern when one is set, and
+    /// built-in outbounds like DIRECT are never candidates.
+    fn best_candidate(&self, delays: &HashMap<String, Option<u32>>, current: &str, nodes: &[String]) -> Option<String> {
+        let healthy_delay = |node: &String| {
+            delays
+                .get(node)
+                .copied()
+                .flatten()
+                .filter(|&delay| delay <= self.config.max_delay_ms)
+        };
+
+        let preferred = self.config.preferred_nodes.iter().find(|preferred| {
+            *preferred != current && nodes.contains(preferred) && self.is_candidate(preferred) && healthy_delay(preferred).is_some()
+        });
+        if let Some(preferred) = preferred {
+            return Some(preferred.clone());
+        }
+
+        nodes
+            .iter()
+            .filter(|node| *node != current && !self.config.preferred_nodes.contains(node) && self.is_candidate(node))
+            .filter_map(|node| Some((node, healthy_delay(node)?)))
+            .min_by_key(|(_, delay)| *delay)
+            .map(|(node, _)| node.clone())
+    }
+
+    /// DIRECT nearly always wins a delay test, but failing over to it would
+    /// quietly stop proxying the group's traffic.
+    fn is_candidate(&self, node: &str) -> bool {
+        !BUILTIN_NODES.contains(&node) && self.node_pattern.as_ref().is_none_or(|pattern| pattern.is_match(node))
+    }
+}
+
+/// Delay of each distinct node, tested in parallel; None for nodes that failed.
+async fn test_delays(clash_api: &ClashApi, config: &Config, nodes: &[&str]) -> HashMap<String, Option<u32>> {
+    let mut tasks = JoinSet::new();
+    let mut delays = HashMap::new();
+    for &node in nodes {
+        if delays.insert(node.to_string(), None).is_some() {
+            continue;
+        }
+        let clash_api = clash_api.clone();
+        let node = node.to_string();
+        let url = config.delay_test_url.clone();
+        let timeout = config.delay_test_timeout;
+        tasks.spawn(async move {
+            let delay = clash_api.test_node_delay(&node, &url, timeout).await;
+            (node, delay)
+        });
+    }
+
+    while let Some(joined) = tasks.join_next().await {
+        if let Ok((node, Ok(delay))) = joined {
+            delays.insert(node, Some(delay));
+        }
+    }
+    delays
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn watchdog(preferred_nodes: &[&str], node_pattern: &str) -> Watchdog {
+        Watchdog::new(&WatchdogConfig {
+            max_delay_ms: 1000,
+            preferred_nodes: preferred_nodes.iter().map(|node| node.to_string()).collect(),
+            node_pattern: node_pattern.to_string(),
+            ..WatchdogConfig::default()
+        })
+        .unwrap()
+    }
+
+    fn nodes(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn delays(entries: &[(&str, Option<u32>)]) -> HashMap<String, Option<u32>> {
+        entries.iter().map(|(node, delay)| (node.to_string(), *delay)).collect()
+    }
+
+    #[test]
+    fn picks_the_fastest_healthy_node_other_than_the_current_one() {
+        let delays = delays(&[("HK", Some(10)), ("JP", Some(80)), ("US", Some(200))]);
+        let best = watchdog(&[], "").best_candidate(&delays, "HK", &nodes(&["HK", "JP", "US"]));
+        assert_eq!(best.as_deref(), Some("JP"));
+    }
+
+    #[test]
+    fn a_healthy_preferred_node_wins_over_a_faster_one() {
+        let delays = delays(&[("JP", Some(20)), ("US", Some(400)), ("SG", Some(300))]);
+        let best = watchdog(&["US", "SG"], "").best_candidate(&delays, "HK", &nodes(&["HK", "JP", "SG", "US"]));
+        assert_eq!(best.as_deref(), Some("US"));
+    }
+
+    #[test]
+    fn preferred_nodes_that_are_down_or_too_slow_are_skipped() {
+        let delays = delays(&[("US", None), ("SG", Some(5000)), ("TW", Some(300)), ("JP", Some(20))]);
+        let nodes = nodes(&["HK", "JP", "SG", "TW", "US"]);
+        let best = watchdog(&["US", "SG", "TW"], "").best_candidate(&delays, "HK", &nodes);
+        assert_eq!(best.as_deref(), Some("TW"));
+
+        // With no healthy preferred node the fastest other node is used
+        let best = watchdog(&["US", "SG"], "").best_candidate(&delays, "HK", &nodes);
+        assert_eq!(best.as_deref(), Some("JP"));
+    }
+
+    #[test]
+    fn builtin_outbounds_are_never_picked() {
+        let delays = delays(&[("DIRECT", Some(1)), ("REJECT", Some(1)), ("REJECT-DROP", Some(1)), ("JP", Some(90))]);
+        let nodes = nodes(&["HK", "DIRECT", "REJECT", "REJECT-DROP", "JP"]);
+        let best = watchdog(&["DIRECT"], "").best_candidate(&delays, "HK", &nodes);
+        assert_eq!(best.as_deref(), Some("JP"));
+    }
+
+    #[test]
+    fn the_pattern_filters_preferred_and_fallback_nodes() {
+        let delays = delays(&[("JP 01", Some(10)), ("HK 02", Some(300)), ("US 01", Some(50))]);
+        let nodes = nodes(&["HK 01", "JP 01", "HK 02", "US 01"]);
+        let best = watchdog(&["JP 01"], "^HK").best_candidate(&delays, "HK 01", &nodes);
+        assert_eq!(best.as_deref(), Some("HK 02"));
+    }
+
+    #[test]
+    fn nothing_healthy_means_no_switch() {
+        let unhealthy = delays(&[("JP", None), ("US", Some(5000)), ("DIRECT", Some(1))]);
+        let best = watchdog(&["JP"], "").best_candidate(&unhealthy, "HK", &nodes(&["HK", "JP", "US", "DIRECT"]));
+        assert_eq!(best, None);
+
+        // The current node is never its own replacement
+        let only_current = delays(&[("HK", Some(10))]);
+        assert_eq!(watchdog(&["HK"], "").best_candidate(&only_current, "HK", &nodes(&["HK"])), None);
+    }
+
+    #[test]
+    fn candidates_exclude_builtins_and_follow_the_pattern() {
+        let any = watchdog(&[], "");
+        assert!(any.is_candidate("HK 01"));
+        assert!(BUILTIN_NODES.iter().all(|node| !any.is_candidate(node)));
+
+        let hong_kong = watchdog(&[], "^HK");
+        assert!(hong_kong.is_candidate("HK 01"));
+        assert!(!hong_kong.is_candidate("JP 01"));
+    }
+}